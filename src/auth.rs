@@ -41,7 +41,7 @@ impl<'r> FromRequest<'r> for WriteAuthorization {
         let cached = request.local_cache(|| {
             // If not set, get from the [`CsrfCheckProof`]
             let proof: &Option<CsrfCheckProof> = request.local_cache(|| None);
-            proof.as_ref().cloned().map_or_else(
+            proof.clone().map_or_else(
                 || Self::IPromiseThisIsABackgroundJobNotTiedToARequest,
                 Self::from,
             )
@@ -1,4 +1,7 @@
-use crate::{ConnectionPool, Error, ReadConnection, WriteAuthorization, WriteConnection};
+use crate::{
+    read::ReadCache, ConnectionPool, Error, ReadConnection, TransactionError, WriteAuthorization,
+    WriteConnection,
+};
 
 use rocket::{
     http::Status,
@@ -10,14 +13,17 @@ type Result<T, E = Error> = anyhow::Result<T, E>;
 
 /// Struct representing an authorized connector.
 /// This connector is pre-authorized, meaning it can create write connections directly.
+///
+/// Reads made through it share a connection in the same way as a [`crate::Connector`]'s.
 #[derive(Clone)]
 pub struct AuthorizedConnector<'pool, DB> {
     pub(crate) pool: &'pool ConnectionPool<DB>,
+    pub(crate) read_cache: Option<&'pool ReadCache<DB>>,
     pub(crate) authorization: WriteAuthorization,
 }
 
-impl<'pool, DB: 'static> AuthorizedConnector<'pool, DB> {
-    /// Get a read-only connection from the pool.
+impl<DB: 'static> AuthorizedConnector<'_, DB> {
+    /// Get a read-only connection from the pool. This always checks out a new connection.
     pub async fn read(&self) -> Result<ReadConnection<DB>> {
         self.pool.get_read().await
     }
@@ -27,38 +33,83 @@ impl<'pool, DB: 'static> AuthorizedConnector<'pool, DB> {
         self.pool.get_write(self.authorization.clone()).await
     }
 
-    // TODO: Investigate caching the read connection
-    /// Get a read-only connection from the pool and run the provided function
-    /// against the connection
+    /// Get a read-only connection (the request's cached one, if any) and run the
+    /// provided function against the connection
     pub async fn connect_and_read<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(&Connection) -> R + Send,
-        R: Send,
+        F: FnOnce(&Connection) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        self.pool.connect_and_read(f).await
+        match self.read_cache {
+            Some(cache) => cache.get(self.pool).await?.run(f).await,
+            None => self.pool.connect_and_read(f).await,
+        }
     }
 
-    /// Get a read-only connection from the pool and run the provided function against
-    /// the connection inside a transaction
+    /// Get a read-only connection (the request's cached one, if any) and run the
+    /// provided function against the connection inside a transaction
     pub async fn connect_and_read_with_transaction<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(Transaction) -> R + Send,
-        R: Send,
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        self.pool.connect_and_read_with_transaction(f).await
+        match self.read_cache {
+            Some(cache) => cache.get(self.pool).await?.run_with_transaction(f).await,
+            None => self.pool.connect_and_read_with_transaction(f).await,
+        }
     }
 
     /// Get a write connection from the pool and run the provided function against
     /// the connection inside a transaction
     pub async fn connect_and_write<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(Transaction) -> R + Send,
-        R: Send,
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
     {
         self.pool
             .connect_and_write(self.authorization.clone(), f)
             .await
     }
+
+    /// Get a write connection from the pool and run the provided function against
+    /// the connection inside a transaction, committing it if the function returns `Ok`
+    /// and rolling it back if it returns `Err`.
+    pub async fn try_connect_and_write<F, R, E>(&self, f: F) -> Result<R, TransactionError<E>>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        self.pool
+            .try_connect_and_write(self.authorization.clone(), f)
+            .await
+    }
+
+    /// Get a write connection from the pool and run the provided function against
+    /// the connection inside a transaction, committing it if the function returns `Ok`.
+    /// The transaction is retried if the database is busy or locked.
+    pub async fn connect_and_write_with_retry<F, R>(&self, f: F) -> Result<R>
+    where
+        F: Fn(&Transaction) -> Result<R, rusqlite::Error> + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        self.pool
+            .connect_and_write_with_retry(self.authorization.clone(), f)
+            .await
+    }
+
+    /// Run the provided function against the writer inside a savepoint, committing
+    /// if it returns `Ok`, coalescing concurrent calls if group commit is configured.
+    pub async fn connect_and_write_grouped<F, R, E>(&self, f: F) -> Result<Result<R, E>>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        self.pool
+            .connect_and_write_grouped(self.authorization.clone(), f)
+            .await
+    }
 }
 
 /// Support downgrading to allow passing this to methods that only need a read-only connection
@@ -66,6 +117,7 @@ impl<'pool, DB> From<AuthorizedConnector<'pool, DB>> for crate::Connector<'pool,
     fn from(authorized: AuthorizedConnector<'pool, DB>) -> Self {
         Self {
             pool: authorized.pool,
+            read_cache: authorized.read_cache,
         }
     }
 }
@@ -98,6 +150,7 @@ impl<'r, DB: 'static> FromRequest<'r> for AuthorizedConnector<'r, DB> {
                     |pool| {
                         Outcome::Success(AuthorizedConnector {
                             pool,
+                            read_cache: Some(request.local_cache(ReadCache::default)),
                             authorization,
                         })
                     },
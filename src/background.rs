@@ -0,0 +1,186 @@
+//! Pieces shared by the crate's background workers, i.e. the job queue and the scheduler.
+
+use crate::{
+    builtin_migrations, config::Config, util::unix_millis_after, ConnectionPool, Database,
+    TransactionError, WriteAuthorization,
+};
+
+use std::{future::Future, time::Duration};
+
+use anyhow::anyhow;
+use rocket::{
+    fairing::{self, AdHoc},
+    Build, Rocket,
+};
+use rusqlite::{types::Value, ToSql};
+use tokio::sync::watch;
+
+pub const AUTHORIZATION: WriteAuthorization =
+    WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest;
+
+/// Run the given statement on the writer, with the given named parameters.
+async fn execute<DB: 'static>(
+    pool: &ConnectionPool<DB>,
+    sql: &'static str,
+    params: Vec<(&'static str, Value)>,
+) -> Result<(), TransactionError<rusqlite::Error>> {
+    pool.try_connect_and_write(AUTHORIZATION, move |transaction| {
+        let params: Vec<(&str, &dyn ToSql)> = params
+            .iter()
+            .map(|(name, value)| (*name, value as &dyn ToSql))
+            .collect();
+        transaction
+            .prepare_cached(sql)?
+            .execute(params.as_slice())?;
+        Ok(())
+    })
+    .await
+}
+
+/// A row reserved by this instance for as long as the work it stands for runs.
+pub struct Lease {
+    /// What the row stands for, for logging.
+    pub description: String,
+    /// How long the row is reserved for each time the lease is extended.
+    pub duration: Duration,
+    /// Statement which extends the lease, given `:lease_until` along with the key. It
+    /// should only touch the row if it still belongs to this lease, in case the lease
+    /// ran out and someone else took the row over in the meantime.
+    pub renew: &'static str,
+    /// The parameters identifying the row, and which lease on it this is.
+    pub key: Vec<(&'static str, Value)>,
+}
+
+impl Lease {
+    /// Run the given statement against the row, with the given parameters along with the key.
+    pub async fn execute<DB: 'static>(
+        &self,
+        pool: &ConnectionPool<DB>,
+        sql: &'static str,
+        mut params: Vec<(&'static str, Value)>,
+    ) -> Result<(), TransactionError<rusqlite::Error>> {
+        params.extend(self.key.iter().cloned());
+        execute(pool, sql, params).await
+    }
+
+    /// Keep extending the lease.
+    async fn renew<DB: 'static>(&self, pool: &ConnectionPool<DB>) {
+        loop {
+            tokio::time::sleep(self.duration / 2).await;
+            let lease_until = unix_millis_after(self.duration);
+            if let Err(e) = self
+                .execute(pool, self.renew, vec![(":lease_until", lease_until.into())])
+                .await
+            {
+                rocket::error!(
+                    "failed to renew the lease on {} on {}: {}",
+                    self.description,
+                    pool.name(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Run the given work, extending the lease until it finishes. The work is spawned,
+    /// so that a panic only takes it out, and is returned as an error.
+    pub async fn run<DB: 'static>(
+        &self,
+        pool: &ConnectionPool<DB>,
+        work: impl Future<Output = anyhow::Result<()>> + Send + 'static,
+    ) -> anyhow::Result<()> {
+        let handle = tokio::spawn(work);
+        let result = tokio::select! {
+            result = handle => result,
+            () = self.renew(pool) => unreachable!("leases are renewed until the work finishes"),
+        };
+        result.unwrap_or_else(|e| Err(anyhow!("panicked: {e}")))
+    }
+}
+
+/// Fairing for a background worker with the given name. Once the database's pool
+/// exists, the worker's tables are created, then it is set up by the given function.
+pub fn fairing<DB, F, Fut>(
+    name: &'static str,
+    component: &'static str,
+    migrations: &'static [&'static str],
+    setup: F,
+) -> AdHoc
+where
+    DB: Database,
+    F: FnOnce(Rocket<Build>, ConnectionPool<DB>, Config) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = fairing::Result> + Send,
+{
+    fairing_inner(name, component, migrations, setup, false)
+}
+
+fn fairing_inner<DB, F, Fut>(
+    name: &'static str,
+    component: &'static str,
+    migrations: &'static [&'static str],
+    setup: F,
+    deferred: bool,
+) -> AdHoc
+where
+    DB: Database,
+    F: FnOnce(Rocket<Build>, ConnectionPool<DB>, Config) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = fairing::Result> + Send,
+{
+    AdHoc::try_on_ignite(name, move |rocket| async move {
+        let config = match Config::from(DB::NAME, &rocket) {
+            Ok(config) => config,
+            Err(e) => {
+                rocket::error!("Error configuring database {}: {}", DB::NAME, e);
+                return Err(rocket);
+            }
+        };
+        let Some(pool) = ConnectionPool::<DB>::get_pool(&rocket).cloned() else {
+            // The database's fairing may have been attached after this one, in
+            // which case it will have run by the time this runs again.
+            if !deferred {
+                return Ok(rocket.attach(fairing_inner(name, component, migrations, setup, true)));
+            }
+            rocket::error!(
+                "the {} for {} needs the database fairing to be attached",
+                name,
+                DB::NAME
+            );
+            return Err(rocket);
+        };
+        let migrated = pool
+            .try_connect_and_write(AUTHORIZATION, move |transaction| {
+                builtin_migrations::migrate(transaction, component, migrations)
+            })
+            .await;
+        if let Err(e) = migrated {
+            rocket::error!(
+                "failed to create the tables for the {} for {}: {}",
+                name,
+                DB::NAME,
+                e
+            );
+            return Err(rocket);
+        }
+        setup(rocket, pool, config).await
+    })
+}
+
+/// Spawn the given worker once rocket has launched, and tell it to stop when rocket shuts down.
+pub fn spawn_worker<F, Fut>(rocket: Rocket<Build>, name: &'static str, worker: F) -> Rocket<Build>
+where
+    F: FnOnce(watch::Receiver<bool>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (stop, stopped) = watch::channel(false);
+    rocket
+        .attach(AdHoc::on_liftoff(name, move |_| {
+            Box::pin(async move {
+                tokio::spawn(worker(stopped));
+            })
+        }))
+        .attach(AdHoc::on_shutdown(name, move |_| {
+            Box::pin(async move {
+                let _ = stop.send(true);
+            })
+        }))
+}
@@ -186,8 +186,7 @@ impl BatchedBulkValuesClause {
                             )
                         })
                     })
-                    .collect::<Result<Vec<Output>, rusqlite::Error>>()?
-                    .into_iter(),
+                    .collect::<Result<Vec<Output>, rusqlite::Error>>()?,
             );
             if this_batch_size < batch_size {
                 statement.discard();
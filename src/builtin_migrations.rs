@@ -0,0 +1,39 @@
+use rusqlite::{OptionalExtension, Transaction};
+
+// The application's migrations track their version in user_version, so the
+// crate's own tables keep track of theirs separately.
+const CREATE_VERSIONS: &str = "CREATE TABLE IF NOT EXISTS rocket_sqlite_rw_pool_migrations (
+    component TEXT PRIMARY KEY NOT NULL,
+    version INTEGER NOT NULL
+)";
+
+/// Apply whichever of the migrations for one of the crate's own components
+/// haven't been applied yet, in order.
+pub fn migrate(
+    transaction: &Transaction,
+    component: &str,
+    migrations: &[&str],
+) -> Result<(), rusqlite::Error> {
+    transaction.execute_batch(CREATE_VERSIONS)?;
+    let version: i64 = transaction
+        .query_row(
+            "SELECT version FROM rocket_sqlite_rw_pool_migrations WHERE component = ?",
+            [component],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or_default();
+    let applied = usize::try_from(version).unwrap_or_default();
+    if applied >= migrations.len() {
+        return Ok(());
+    }
+    for migration in &migrations[applied..] {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.execute(
+        "INSERT INTO rocket_sqlite_rw_pool_migrations (component, version) VALUES (?1, ?2)
+        ON CONFLICT (component) DO UPDATE SET version = excluded.version",
+        (component, migrations.len()),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use crate::Error;
+
+use std::{cell::RefCell, future::Future, pin::Pin};
+
+use rusqlite::Transaction;
+
+/// A side effect waiting for its transaction to finish.
+pub type Callback = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The callbacks registered while running a write.
+#[derive(Default)]
+pub struct Callbacks {
+    on_commit: Vec<Callback>,
+    on_rollback: Vec<Callback>,
+    /// Callbacks to run however the transaction finishes, as what they were
+    /// registered for was already rolled back to a savepoint.
+    always: Vec<Callback>,
+}
+
+impl Callbacks {
+    /// The callbacks to run, given whether the transaction committed.
+    pub fn finish(mut self, committed: bool) -> Vec<Callback> {
+        let mut callbacks = if committed {
+            self.on_commit
+        } else {
+            self.on_rollback
+        };
+        callbacks.append(&mut self.always);
+        callbacks
+    }
+
+    /// Add the callbacks registered inside a savepoint, given whether it was released.
+    fn merge(&mut self, mut nested: Self, released: bool) {
+        if released {
+            self.on_commit.append(&mut nested.on_commit);
+            self.on_rollback.append(&mut nested.on_rollback);
+        } else {
+            // Whatever the rest of the transaction does, this part of it won't be committed.
+            self.always.append(&mut nested.on_rollback);
+        }
+        self.always.append(&mut nested.always);
+    }
+}
+
+thread_local! {
+    /// Where callbacks registered on this thread go, if a write is running on it.
+    static REGISTERED: RefCell<Option<Callbacks>> = const { RefCell::new(None) };
+}
+
+/// Puts back whatever was being collected before, even if the function panics.
+struct Scope {
+    previous: Option<Callbacks>,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        REGISTERED.with(|registered| *registered.borrow_mut() = previous);
+    }
+}
+
+/// Run the given function, collecting the callbacks registered while it runs.
+pub fn collect<R>(f: impl FnOnce() -> R) -> (R, Callbacks) {
+    let scope = Scope {
+        previous: REGISTERED.with(|registered| registered.replace(Some(Callbacks::default()))),
+    };
+    let value = f();
+    let callbacks = REGISTERED.with(|registered| registered.borrow_mut().take());
+    drop(scope);
+    (
+        value,
+        callbacks.expect("internal invariant broken: callbacks are collected until the scope ends"),
+    )
+}
+
+/// Run the given function inside a savepoint, keeping the callbacks registered while it
+/// runs apart until [`finish_savepoint`] is told whether the savepoint was released.
+/// If no callbacks are being collected, e.g. on a reader, the function is just run.
+pub fn savepoint<R>(f: impl FnOnce() -> R) -> (R, Option<Callbacks>) {
+    if REGISTERED.with(|registered| registered.borrow().is_none()) {
+        return (f(), None);
+    }
+    let (value, callbacks) = collect(f);
+    (value, Some(callbacks))
+}
+
+/// Hand the callbacks registered inside a savepoint back to the enclosing transaction,
+/// given whether the savepoint was released. Those which can no longer run are dropped.
+pub fn finish_savepoint(callbacks: Option<Callbacks>, released: bool) {
+    let Some(callbacks) = callbacks else {
+        return;
+    };
+    REGISTERED.with(|registered| {
+        registered
+            .borrow_mut()
+            .as_mut()
+            .expect("internal invariant broken: savepoints are finished inside their scope")
+            .merge(callbacks, released);
+    });
+}
+
+fn register(callback: Callback, committed: bool) -> Result<(), Error> {
+    REGISTERED.with(|registered| {
+        let mut registered = registered.borrow_mut();
+        let callbacks = registered.as_mut().ok_or(Error::CallbacksOutsideWrite)?;
+        if committed {
+            callbacks.on_commit.push(callback);
+        } else {
+            callbacks.on_rollback.push(callback);
+        }
+        Ok(())
+    })
+}
+
+/// Side effects which should only happen once a write transaction has finished.
+///
+/// For example, sending an email only if the transaction commits. They are spawned once
+/// the writer has been released, i.e. once the [`WriteConnection`](crate::WriteConnection)
+/// they were registered through is dropped. They are not run if the write panics.
+/// Callbacks registered inside a savepoint which is rolled back are treated as if the
+/// transaction was rolled back, whatever happens to the rest of it.
+pub trait TransactionCallbacks {
+    /// Run the given future if the transaction commits.
+    ///
+    /// Returns [`Error::CallbacksOutsideWrite`] if called outside of a function run by
+    /// the pool against the writer, e.g. on a read transaction.
+    fn on_commit<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: Future<Output = ()> + Send + 'static;
+
+    /// Run the given future if the transaction is rolled back.
+    ///
+    /// Returns [`Error::CallbacksOutsideWrite`] if called outside of a function run by
+    /// the pool against the writer, e.g. on a read transaction.
+    fn on_rollback<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: Future<Output = ()> + Send + 'static;
+}
+
+impl TransactionCallbacks for Transaction<'_> {
+    fn on_commit<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        register(Box::pin(f), true)
+    }
+
+    fn on_rollback<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        register(Box::pin(f), false)
+    }
+}
+
+/// Spawn the given callbacks.
+pub fn spawn(callbacks: Vec<Callback>) {
+    for callback in callbacks {
+        tokio::spawn(callback);
+    }
+}
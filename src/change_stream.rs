@@ -0,0 +1,150 @@
+use crate::{query_with_params, ConnectionPool};
+
+use std::{collections::BTreeSet, marker::PhantomData, sync::Arc};
+
+use rocket::{
+    http::Status,
+    request::Request,
+    response::{
+        self,
+        stream::{Event, EventStream},
+        Responder,
+    },
+};
+use rusqlite::Connection;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+type Query = Arc<dyn Fn(&Connection) -> anyhow::Result<serde_json::Value> + Send + Sync>;
+
+/// Run the query against a read connection, turning its result into an event. If it
+/// fails, the error is logged and there is no event, which ends the stream.
+async fn run_query<DB: 'static>(pool: &ConnectionPool<DB>, query: &Query) -> Option<Event> {
+    let query = Arc::clone(query);
+    let result = pool
+        .connect_and_read(move |connection| query(connection))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+    match result {
+        Ok(value) => Some(Event::json(&value)),
+        Err(e) => {
+            rocket::error!(
+                "failed to run change stream query on {}: {}",
+                pool.name(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Responder which streams the rows committed to the database to the client as
+/// Server-Sent Events, for as long as the client stays connected.
+///
+/// By default each change is sent as JSON. If a query is set, its result is sent
+/// as JSON instead: once up front, then again whenever the watched tables change.
+/// If the stream falls too far behind, changes are skipped: the query is re-run,
+/// or, without a query, a `lagged` event with the number of skipped changes is sent.
+pub struct ChangeStream<DB> {
+    tables: BTreeSet<String>,
+    query: Option<Query>,
+    _marker: PhantomData<fn() -> DB>,
+}
+
+impl<DB> Default for ChangeStream<DB> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<DB> ChangeStream<DB> {
+    /// Stream the changes made to every table.
+    pub const fn new() -> Self {
+        Self {
+            tables: BTreeSet::new(),
+            query: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Only stream the changes made to the given table. Can be called more than once.
+    #[must_use]
+    pub fn table(mut self, table: impl Into<String>) -> Self {
+        self.tables.insert(table.into());
+        self
+    }
+
+    /// Send the result of the given query (via [`query_with_params`]) instead of the changes.
+    #[must_use]
+    pub fn query<Input, Output>(mut self, sql: impl Into<String>, params: Input) -> Self
+    where
+        Input: Serialize + Send + Sync + 'static,
+        Output: DeserializeOwned + Serialize,
+    {
+        let sql = sql.into();
+        self.query = Some(Arc::new(move |connection| {
+            let rows: Vec<Output> = query_with_params(&sql, connection, &params)?;
+            Ok(serde_json::to_value(rows)?)
+        }));
+        self
+    }
+}
+
+impl<'r, DB: 'static> Responder<'r, 'r> for ChangeStream<DB> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        let Some(pool) = ConnectionPool::<DB>::get_pool(request.rocket()) else {
+            rocket::error!(
+                "Missing database fairing for `{}`",
+                std::any::type_name::<DB>()
+            );
+            return Err(Status::InternalServerError);
+        };
+        let pool = pool.clone();
+        // Subscribed before running the query, so that no changes are missed in between.
+        let mut changes = pool.subscribe_changes();
+        let Self { tables, query, .. } = self;
+        let watched = move |table: &str| tables.is_empty() || tables.contains(table);
+
+        EventStream! {
+            if let Some(query) = &query {
+                match run_query(&pool, query).await {
+                    Some(event) => yield event,
+                    None => return,
+                }
+            }
+            loop {
+                match changes.recv().await {
+                    Ok(change) if !watched(&change.table) => {}
+                    Ok(change) if query.is_none() => yield Event::json(&change),
+                    Err(RecvError::Lagged(skipped)) if query.is_none() => {
+                        yield Event::data(skipped.to_string()).event("lagged");
+                    }
+                    Err(RecvError::Closed) => return,
+                    // A watched table changed, or changes were skipped, so the
+                    // query's result may have changed.
+                    Ok(_) | Err(RecvError::Lagged(_)) => {
+                        // The query is about to be re-run anyway, so anything else
+                        // which has already arrived doesn't need it to be run again.
+                        loop {
+                            match changes.try_recv() {
+                                Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                                Err(TryRecvError::Empty) => break,
+                                Err(TryRecvError::Closed) => return,
+                            }
+                        }
+                        if let Some(query) = &query {
+                            match run_query(&pool, query).await {
+                                Some(event) => yield event,
+                                None => return,
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .respond_to(request)
+    }
+}
+
+crate::define_sentinel_for_gettable_connection!(ChangeStream);
@@ -0,0 +1,187 @@
+use std::{
+    cell::RefCell,
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{hooks::Action, Connection};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// How many changes can be buffered for a subscriber before it starts missing them.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// The kind of change made to a row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A row changed by a committed write.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct Change {
+    /// The schema the table is in, i.e. `main` or the name of an attached database.
+    pub schema: String,
+    /// The table the row is in.
+    pub table: String,
+    /// What happened to the row.
+    pub operation: ChangeOperation,
+    /// The rowid of the row.
+    pub rowid: i64,
+}
+
+#[derive(Default)]
+struct Buffers {
+    /// Changes made by the transaction in progress.
+    pending: Vec<Change>,
+    /// Changes made by a transaction which is committing, which can't be
+    /// published until the commit has finished.
+    committing: Vec<Change>,
+    /// Whether the last transaction to finish committed, if one has finished
+    /// since this was last taken.
+    committed: Option<bool>,
+}
+
+thread_local! {
+    /// The notifier for the writer a function is running against on this thread, if any.
+    static ACTIVE: RefCell<Option<Arc<ChangeNotifier>>> = const { RefCell::new(None) };
+}
+
+/// Puts back whichever notifier was active before, even if the function panics.
+struct Scope {
+    previous: Option<Arc<ChangeNotifier>>,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        ACTIVE.with(|active| *active.borrow_mut() = previous);
+    }
+}
+
+/// Where a savepoint opened on the writer starts within the changes pending on it.
+pub struct SavepointMark {
+    notifier: Arc<ChangeNotifier>,
+    pending: usize,
+}
+
+impl SavepointMark {
+    /// Forget the changes made since the savepoint was opened, as it is being rolled back.
+    pub fn rollback(self) {
+        self.notifier.buffers().pending.truncate(self.pending);
+    }
+}
+
+/// Note where a savepoint being opened on this thread starts, if it is being opened on the writer.
+/// Only the crate's own savepoints do this, so the changes made inside any others are kept
+/// even if they are rolled back.
+pub fn savepoint() -> Option<SavepointMark> {
+    ACTIVE.with(|active| {
+        active.borrow().as_ref().map(|notifier| SavepointMark {
+            notifier: Arc::clone(notifier),
+            pending: notifier.buffers().pending.len(),
+        })
+    })
+}
+
+/// Collects the changes made on the writer, and publishes them once they are committed.
+/// Also keeps track of whether its transactions commit.
+pub struct ChangeNotifier {
+    sender: broadcast::Sender<Change>,
+    buffers: Mutex<Buffers>,
+}
+
+impl ChangeNotifier {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            buffers: Mutex::default(),
+        })
+    }
+
+    fn buffers(&self) -> std::sync::MutexGuard<'_, Buffers> {
+        self.buffers
+            .lock()
+            .expect("internal invariant broken: change buffers are never poisoned")
+    }
+
+    /// Subscribe to changes committed from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+        self.sender.subscribe()
+    }
+
+    /// Track the changes made on the given connection.
+    pub fn install(self: &Arc<Self>, connection: &Connection) {
+        let notifier = Arc::clone(self);
+        connection.update_hook(Some(move |action, schema: &str, table: &str, rowid| {
+            // Nobody is listening, so don't bother keeping track.
+            if notifier.sender.receiver_count() == 0 {
+                return;
+            }
+            let operation = match action {
+                Action::SQLITE_INSERT => ChangeOperation::Insert,
+                Action::SQLITE_UPDATE => ChangeOperation::Update,
+                Action::SQLITE_DELETE => ChangeOperation::Delete,
+                _ => return,
+            };
+            notifier.buffers().pending.push(Change {
+                schema: schema.to_owned(),
+                table: table.to_owned(),
+                operation,
+                rowid,
+            });
+        }));
+        let notifier = Arc::clone(self);
+        connection.commit_hook(Some(move || {
+            let mut buffers = notifier.buffers();
+            let pending = mem::take(&mut buffers.pending);
+            buffers.committing.extend(pending);
+            buffers.committed = Some(true);
+            // Let the commit go ahead.
+            false
+        }));
+        let notifier = Arc::clone(self);
+        connection.rollback_hook(Some(move || {
+            let mut buffers = notifier.buffers();
+            buffers.pending.clear();
+            // Whatever was being committed was rolled back instead, e.g. as the commit failed.
+            buffers.committing.clear();
+            buffers.committed = Some(false);
+        }));
+    }
+
+    /// Run the given function against the writer, so that savepoints it rolls back
+    /// also roll back the changes made inside them.
+    pub fn track<R>(self: &Arc<Self>, f: impl FnOnce() -> R) -> R {
+        let scope = Scope {
+            previous: ACTIVE.with(|active| active.replace(Some(Arc::clone(self)))),
+        };
+        let value = f();
+        drop(scope);
+        value
+    }
+
+    /// Whether the last transaction to finish on the connection committed, if
+    /// one has finished since this was last called.
+    pub fn take_committed(&self) -> Option<bool> {
+        self.buffers().committed.take()
+    }
+
+    /// Publish the changes committed on the given connection, once it is no
+    /// longer inside a transaction.
+    pub fn publish(&self, connection: &Connection) {
+        if !connection.is_autocommit() {
+            return;
+        }
+        let committed = mem::take(&mut self.buffers().committing);
+        for change in committed {
+            // Nobody is listening any more.
+            if self.sender.send(change).is_err() {
+                break;
+            }
+        }
+    }
+}
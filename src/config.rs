@@ -1,5 +1,7 @@
 use crate::pragmas::Pragmas;
 
+use std::collections::BTreeMap;
+
 use rocket::{
     figment::{providers::Serialized, Error, Figment},
     Build, Rocket,
@@ -19,13 +21,290 @@ pub struct MigrationConfig {
     pub(crate) first_to: Option<usize>,
 }
 
+/// Configuration for coalescing concurrent writes into a single transaction.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GroupCommitConfig {
+    /// The maximum number of writes to coalesce into a single transaction.
+    pub(crate) max_batch_size: usize,
+
+    /// The maximum amount of time (in milliseconds) to wait for more writes to arrive before committing.
+    pub(crate) max_linger: u64,
+}
+
+mod priority_defaults {
+    pub const fn request() -> u8 {
+        2
+    }
+
+    pub const fn background_job() -> u8 {
+        1
+    }
+
+    pub const fn logging() -> u8 {
+        0
+    }
+
+    pub const fn max_skips() -> usize {
+        8
+    }
+}
+
+/// Priorities for acquiring the writer, based on the [`crate::WriteAuthorization`] provided.
+/// Writers with a higher priority are served first.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WritePriorityConfig {
+    /// Priority of writes from requests which passed CSRF checks.
+    #[serde(default = "priority_defaults::request")]
+    pub(crate) request: u8,
+
+    /// Priority of writes from background jobs.
+    #[serde(default = "priority_defaults::background_job")]
+    pub(crate) background_job: u8,
+
+    /// Priority of writes from logging endpoints.
+    #[serde(default = "priority_defaults::logging")]
+    pub(crate) logging: u8,
+
+    /// The number of times a waiting writer can be overtaken by higher priority writers
+    /// before it is served next, regardless of priority.
+    #[serde(default = "priority_defaults::max_skips")]
+    pub(crate) max_skips: usize,
+}
+
+impl Default for WritePriorityConfig {
+    fn default() -> Self {
+        Self {
+            request: priority_defaults::request(),
+            background_job: priority_defaults::background_job(),
+            logging: priority_defaults::logging(),
+            max_skips: priority_defaults::max_skips(),
+        }
+    }
+}
+
+/// Configuration for recycling and validating the connections in one of the pools.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct PooledConnectionConfig {
+    /// The maximum amount of time (in seconds) a connection is kept open before it is replaced.
+    /// If set to 0, connections are never replaced. Defaults to 30 minutes.
+    #[serde(default)]
+    pub(crate) max_lifetime: Option<u64>,
+
+    /// A query to run against connections before they are handed out. Connections for which it fails
+    /// are replaced. If not set, connections are only checked for being open.
+    #[serde(default)]
+    pub(crate) validation_query: Option<String>,
+}
+
+/// A database file attached to every connection, under its own schema name.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AttachedDatabase {
+    /// The path or URI of the database file.
+    pub(crate) url: String,
+
+    /// Whether the writer should also attach the database read-only. Readers always attach it read-only.
+    #[serde(default)]
+    pub(crate) read_only: bool,
+}
+
+mod retry_defaults {
+    pub const fn max_attempts() -> u32 {
+        5
+    }
+
+    pub const fn initial_backoff() -> u64 {
+        10
+    }
+
+    pub const fn max_backoff() -> u64 {
+        1000
+    }
+}
+
+/// Configuration for retrying write transactions when the database is busy or locked,
+/// e.g. by another process.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RetryConfig {
+    /// The maximum number of times to attempt the transaction.
+    #[serde(default = "retry_defaults::max_attempts")]
+    pub(crate) max_attempts: u32,
+
+    /// The amount of time (in milliseconds) to wait before the first retry. This doubles after every retry.
+    #[serde(default = "retry_defaults::initial_backoff")]
+    pub(crate) initial_backoff: u64,
+
+    /// The maximum amount of time (in milliseconds) to wait between retries.
+    #[serde(default = "retry_defaults::max_backoff")]
+    pub(crate) max_backoff: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: retry_defaults::max_attempts(),
+            initial_backoff: retry_defaults::initial_backoff(),
+            max_backoff: retry_defaults::max_backoff(),
+        }
+    }
+}
+
+mod jobs_defaults {
+    pub const fn concurrency() -> usize {
+        4
+    }
+
+    pub const fn poll_interval() -> u64 {
+        1000
+    }
+
+    pub const fn lease() -> u64 {
+        60_000
+    }
+
+    pub const fn max_attempts() -> u32 {
+        10
+    }
+
+    pub const fn initial_backoff() -> u64 {
+        1000
+    }
+
+    pub const fn max_backoff() -> u64 {
+        3_600_000
+    }
+}
+
+/// Configuration for the background job worker.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JobsConfig {
+    /// The maximum number of jobs to run at once.
+    #[serde(default = "jobs_defaults::concurrency")]
+    pub(crate) concurrency: usize,
+
+    /// How often (in milliseconds) to check for jobs which are ready to run.
+    #[serde(default = "jobs_defaults::poll_interval")]
+    pub(crate) poll_interval: u64,
+
+    /// How long (in milliseconds) a claimed job is reserved for. The lease is renewed while the job
+    /// runs, so it only runs out if the worker running it goes away, after which the job is retried.
+    #[serde(default = "jobs_defaults::lease")]
+    pub(crate) lease: u64,
+
+    /// The maximum number of times to attempt a job before dead-lettering it.
+    #[serde(default = "jobs_defaults::max_attempts")]
+    pub(crate) max_attempts: u32,
+
+    /// The amount of time (in milliseconds) to wait before the first retry. This doubles after every retry.
+    #[serde(default = "jobs_defaults::initial_backoff")]
+    pub(crate) initial_backoff: u64,
+
+    /// The maximum amount of time (in milliseconds) to wait between retries.
+    #[serde(default = "jobs_defaults::max_backoff")]
+    pub(crate) max_backoff: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: jobs_defaults::concurrency(),
+            poll_interval: jobs_defaults::poll_interval(),
+            lease: jobs_defaults::lease(),
+            max_attempts: jobs_defaults::max_attempts(),
+            initial_backoff: jobs_defaults::initial_backoff(),
+            max_backoff: jobs_defaults::max_backoff(),
+        }
+    }
+}
+
+mod scheduler_defaults {
+    pub const fn poll_interval() -> u64 {
+        1000
+    }
+
+    pub const fn lease() -> u64 {
+        60_000
+    }
+}
+
+/// Configuration for the scheduled task runner.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// How often (in milliseconds) to check for tasks which are due to run.
+    #[serde(default = "scheduler_defaults::poll_interval")]
+    pub(crate) poll_interval: u64,
+
+    /// How long (in milliseconds) a running task is reserved for, so that no other instance runs it.
+    /// The lease is renewed while the task runs, so it only runs out if the instance running it goes away.
+    #[serde(default = "scheduler_defaults::lease")]
+    pub(crate) lease: u64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: scheduler_defaults::poll_interval(),
+            lease: scheduler_defaults::lease(),
+        }
+    }
+}
+
+mod slow_query_defaults {
+    pub const fn sample_rate() -> f64 {
+        1.0
+    }
+}
+
+/// Configuration for logging slow queries.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SlowQueryConfig {
+    /// Queries which take at least this long (in milliseconds) are logged.
+    pub(crate) threshold: u64,
+
+    /// The fraction of slow queries to log, between 0 and 1. Defaults to logging all of them.
+    #[serde(default = "slow_query_defaults::sample_rate")]
+    pub(crate) sample_rate: f64,
+}
+
+mod health_defaults {
+    pub const fn timeout() -> u64 {
+        1000
+    }
+}
+
+/// Configuration for the health check route.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheckConfig {
+    /// The maximum amount of time (in milliseconds) each check can take before the database is considered unhealthy.
+    #[serde(default = "health_defaults::timeout")]
+    pub(crate) timeout: u64,
+
+    /// Whether to also check out the writer. This waits behind other writers, so may fail under heavy write load.
+    #[serde(default)]
+    pub(crate) check_writer: bool,
+
+    /// Whether to run `PRAGMA quick_check`, which reads the entire database.
+    #[serde(default)]
+    pub(crate) quick_check: bool,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            timeout: health_defaults::timeout(),
+            check_writer: false,
+            quick_check: false,
+        }
+    }
+}
+
 // TODO: Think about shared cache, statement cache,
 // Reuses the same configurations as what's provided by rocket itself.
 /// Configuration for a database.
 /// This struct holds all the necessary configuration options for a database connection.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
     /// The URL of the database to connect to.
+    /// If this is `:memory:`, each pool gets its own in-memory database, shared between all its connections.
     pub(crate) url: String,
 
     /// Pragmas to be applied to the database connection.
@@ -47,15 +326,69 @@ pub struct Config {
     /// The maximum amount of time (in milliseconds) to wait when trying to execute a query on the database before giving up.
     pub(crate) busy_timeout: Option<u64>,
 
+    /// The maximum amount of time (in milliseconds) the queries in a single call on a connection can run for
+    /// before they are aborted. If not set, queries can run for as long as they like. With group commit,
+    /// each write in a batch can run for this long, rather than the batch as a whole.
+    #[serde(default)]
+    pub(crate) statement_timeout: Option<u64>,
+
+    /// Configuration for recycling and validating read-only connections.
+    #[serde(default)]
+    pub(crate) readers: PooledConnectionConfig,
+
+    /// Configuration for recycling and validating the write connection.
+    #[serde(default)]
+    pub(crate) writer: PooledConnectionConfig,
+
+    /// The maximum amount of time (in seconds) to wait for the writer to finish when shutting down.
+    pub(crate) shutdown_timeout: u64,
+
+    /// Additional databases to attach to every connection, keyed by schema name.
+    /// They can be used in queries (and migrations) as `schema.table`.
+    #[serde(default)]
+    pub(crate) attach: BTreeMap<String, AttachedDatabase>,
+
     /// Configuration for database migrations.
     /// This includes the version to migrate to and an optional first version to migrate to before the final version.
     #[serde(default)]
     pub(crate) migrate: MigrationConfig,
+
+    /// If set, grouped writes (i.e. those made with `connect_and_write_grouped`) are coalesced into
+    /// a single transaction with a savepoint per caller. If not set, each grouped write runs in its
+    /// own transaction. Other writes are never coalesced, as they control their own transactions.
+    #[serde(default)]
+    pub(crate) group_commit: Option<GroupCommitConfig>,
+
+    /// Priorities for acquiring the writer.
+    #[serde(default)]
+    pub(crate) write_priorities: WritePriorityConfig,
+
+    /// Configuration for the health check route.
+    #[serde(default)]
+    pub(crate) health: HealthCheckConfig,
+
+    /// Configuration for retrying write transactions when the database is busy.
+    #[serde(default)]
+    pub(crate) retry: RetryConfig,
+
+    /// If set, queries slower than the configured threshold are logged, along with their parameters.
+    #[serde(default)]
+    pub(crate) slow_query_log: Option<SlowQueryConfig>,
+
+    /// Configuration for the background job worker, if its fairing is attached.
+    #[serde(default)]
+    pub(crate) jobs: JobsConfig,
+
+    /// Configuration for the scheduled task runner, if its fairing is attached.
+    #[serde(default)]
+    pub(crate) scheduler: SchedulerConfig,
 }
 
 impl Config {
-    pub(crate) fn from(db_name: &str, rocket: &Rocket<Build>) -> Result<Self, Error> {
-        Self::figment(db_name, rocket).extract::<Self>()
+    pub(crate) fn from(db_name: &str, rocket: &Rocket<Build>) -> Result<Self, Box<Error>> {
+        Self::figment(db_name, rocket)
+            .extract::<Self>()
+            .map_err(Box::new)
     }
 
     fn figment(db_name: &str, rocket: &Rocket<Build>) -> Figment {
@@ -69,6 +402,7 @@ impl Config {
         let figment = Figment::from(rocket.figment())
             .focus(&db_key)
             .join(Serialized::default("connect_timeout", 5_i32))
+            .join(Serialized::default("shutdown_timeout", 5_i32))
             .join(Serialized::default("pragmas", Pragmas::default()));
 
         match default_max_read_connections {
@@ -1,5 +1,6 @@
 use crate::{
-    AuthorizedConnector, ConnectionPool, Error, ReadConnection, WriteAuthorization, WriteConnection,
+    read::ReadCache, AuthorizedConnector, ConnectionPool, Error, ReadConnection, TransactionError,
+    WriteAuthorization, WriteConnection,
 };
 
 use rusqlite::{Connection, Transaction};
@@ -7,13 +8,19 @@ use rusqlite::{Connection, Transaction};
 type Result<T, E = Error> = anyhow::Result<T, E>;
 
 /// Connector struct that can be used to get read or write connections from the pool.
+///
+/// When obtained from a request, reads made through it share a single connection
+/// until the handler returns, so it isn't held while the response is sent. While it
+/// is held, it takes up one of the pool's readers: reading from the pool directly in
+/// the same handler needs another one, and waits if `max_read_connections` is 1.
 #[derive(Clone)]
 pub struct Connector<'pool, DB> {
     pub(crate) pool: &'pool ConnectionPool<DB>,
+    pub(crate) read_cache: Option<&'pool ReadCache<DB>>,
 }
 
 impl<'pool, DB: 'static> Connector<'pool, DB> {
-    /// Get a read-only connection from the pool.
+    /// Get a read-only connection from the pool. This always checks out a new connection.
     pub async fn read(&self) -> Result<ReadConnection<DB>> {
         self.pool.get_read().await
     }
@@ -25,6 +32,7 @@ impl<'pool, DB: 'static> Connector<'pool, DB> {
     ) -> AuthorizedConnector<'pool, DB> {
         AuthorizedConnector {
             pool: self.pool,
+            read_cache: self.read_cache,
             authorization,
         }
     }
@@ -37,36 +45,87 @@ impl<'pool, DB: 'static> Connector<'pool, DB> {
         self.pool.get_write(authorization).await
     }
 
-    // TODO: Investigate caching the read connection
-    /// Get a read-only connection from the pool and run the provided function
-    /// against the connection
+    /// Get a read-only connection (the request's cached one, if any) and run the
+    /// provided function against the connection
     pub async fn connect_and_read<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(&Connection) -> R + Send,
-        R: Send,
+        F: FnOnce(&Connection) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        self.pool.connect_and_read(f).await
+        match self.read_cache {
+            Some(cache) => cache.get(self.pool).await?.run(f).await,
+            None => self.pool.connect_and_read(f).await,
+        }
     }
 
-    /// Get a read-only connection from the pool and run the provided function against
-    /// the connection inside a transaction
+    /// Get a read-only connection (the request's cached one, if any) and run the
+    /// provided function against the connection inside a transaction
     pub async fn connect_and_read_with_transaction<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(Transaction) -> R + Send,
-        R: Send,
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        self.pool.connect_and_read_with_transaction(f).await
+        match self.read_cache {
+            Some(cache) => cache.get(self.pool).await?.run_with_transaction(f).await,
+            None => self.pool.connect_and_read_with_transaction(f).await,
+        }
     }
 
     /// Get a write connection from the pool and run the provided function against
     /// the connection inside a transaction
     pub async fn connect_and_write<F, R>(&self, auth: WriteAuthorization, f: F) -> Result<R>
     where
-        F: FnOnce(Transaction) -> R + Send,
-        R: Send,
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
     {
         self.pool.connect_and_write(auth, f).await
     }
+
+    /// Get a write connection from the pool and run the provided function against
+    /// the connection inside a transaction, committing it if the function returns `Ok`
+    /// and rolling it back if it returns `Err`.
+    pub async fn try_connect_and_write<F, R, E>(
+        &self,
+        auth: WriteAuthorization,
+        f: F,
+    ) -> Result<R, TransactionError<E>>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        self.pool.try_connect_and_write(auth, f).await
+    }
+
+    /// Get a write connection from the pool and run the provided function against
+    /// the connection inside a transaction, committing it if the function returns `Ok`.
+    /// The transaction is retried if the database is busy or locked.
+    pub async fn connect_and_write_with_retry<F, R>(
+        &self,
+        auth: WriteAuthorization,
+        f: F,
+    ) -> Result<R>
+    where
+        F: Fn(&Transaction) -> Result<R, rusqlite::Error> + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        self.pool.connect_and_write_with_retry(auth, f).await
+    }
+
+    /// Run the provided function against the writer inside a savepoint, committing
+    /// if it returns `Ok`, coalescing concurrent calls if group commit is configured.
+    pub async fn connect_and_write_grouped<F, R, E>(
+        &self,
+        auth: WriteAuthorization,
+        f: F,
+    ) -> Result<Result<R, E>>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        self.pool.connect_and_write_grouped(auth, f).await
+    }
 }
 
 crate::define_from_request_for_pool_holder!(Connector);
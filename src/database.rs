@@ -0,0 +1,11 @@
+use rocket::{Build, Rocket};
+
+/// Implemented by the types created with [`define_database!`](crate::define_database),
+/// so that code can be generic over them.
+pub trait Database: Send + Sync + 'static {
+    /// The name of the database in the configuration.
+    const NAME: &'static str;
+
+    /// Attach the fairing which sets up this database's pool.
+    fn attach(rocket: Rocket<Build>) -> Rocket<Build>;
+}
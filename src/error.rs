@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 pub type BoxDynError = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(thiserror::Error, Debug)]
@@ -38,4 +40,30 @@ pub enum Error {
     MissingDatabaseFairing(String),
     #[error("Authorization not provided when fetching connection")]
     Unauthorized,
+    #[error("database pool is shutting down")]
+    ShuttingDown,
+    #[error("group commit failed: {0:?}")]
+    GroupCommit(Arc<Self>),
+    #[error("group commit queue has stopped")]
+    GroupCommitStopped,
+    #[error("query interrupted as it took too long")]
+    Interrupted,
+    #[error("query aborted as it exceeded the statement timeout")]
+    StatementTimeout,
+    #[error("couldn't begin a transaction: {0:?}")]
+    TransactionBegin(rusqlite::Error),
+    #[error("database still busy after {0} attempts: {1:?}")]
+    RetriesExhausted(u32, rusqlite::Error),
+    #[error("transaction callbacks can only be registered inside the pool's writes")]
+    CallbacksOutsideWrite,
+}
+
+/// Error from a transaction run with a fallible function, which is committed if
+/// the function returns `Ok` and rolled back if it returns `Err`.
+#[derive(thiserror::Error, Debug)]
+pub enum TransactionError<E> {
+    #[error("transaction aborted: {0:?}")]
+    Aborted(E),
+    #[error("{0}")]
+    Database(#[from] Error),
 }
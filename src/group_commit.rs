@@ -0,0 +1,343 @@
+use crate::{
+    callbacks::{self, Callback, Callbacks},
+    changes::{self, ChangeNotifier},
+    config::GroupCommitConfig,
+    holder::{set_deadline, ConnectionHolder},
+    manager::ConnectionManager,
+    pool::SharedPool,
+    priority::WriterQueue,
+    stats::StatsRecorder,
+    trace,
+    util::run_blocking,
+    ConnectionPool, Error, WriteAuthorization,
+};
+
+use std::{
+    any::Any,
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+use r2d2::PooledConnection;
+use rusqlite::{Transaction, TransactionBehavior};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{timeout_at, Instant},
+};
+
+type Result<T, E = Error> = anyhow::Result<T, E>;
+
+const SAVEPOINT: &str = "SAVEPOINT group_commit";
+const RELEASE: &str = "RELEASE group_commit";
+const ROLLBACK: &str = "ROLLBACK TO group_commit; RELEASE group_commit";
+
+/// What gets sent back to the caller of a grouped write.
+enum Delivery<R, E> {
+    Finished(Result<R, E>),
+    Panicked(Box<dyn Any + Send>),
+    Failed(Error),
+}
+
+/// A write waiting in the queue, with the types of its closure erased.
+trait PendingWrite: Send {
+    /// The authorization the write was submitted with.
+    fn authorization(&self) -> &WriteAuthorization;
+
+    /// Run the write inside its own savepoint of the given transaction, aborting its
+    /// queries if they run for longer than the statement timeout, and returning whether
+    /// they were. An error here means the savepoint could not be cleaned up, and the
+    /// whole transaction must be abandoned.
+    fn run(
+        &mut self,
+        transaction: &Transaction,
+        statement_timeout: Option<Duration>,
+    ) -> Result<bool, rusqlite::Error>;
+
+    /// The callbacks to run, given whether the transaction committed.
+    fn callbacks(&mut self, committed: bool) -> Vec<Callback>;
+
+    /// Deliver the result to the caller once the transaction has finished.
+    fn finish(self: Box<Self>, outcome: Result<(), Arc<Error>>);
+}
+
+struct GroupedWrite<F, R, E> {
+    authorization: WriteAuthorization,
+    f: Option<F>,
+    delivery: Option<Delivery<R, E>>,
+    callbacks: Callbacks,
+    sender: oneshot::Sender<Delivery<R, E>>,
+}
+
+impl<F, R, E> PendingWrite for GroupedWrite<F, R, E>
+where
+    F: FnOnce(&Transaction) -> Result<R, E> + Send,
+    R: Send,
+    E: Send,
+{
+    fn authorization(&self) -> &WriteAuthorization {
+        &self.authorization
+    }
+
+    fn run(
+        &mut self,
+        transaction: &Transaction,
+        statement_timeout: Option<Duration>,
+    ) -> Result<bool, rusqlite::Error> {
+        let f = self
+            .f
+            .take()
+            .expect("internal invariant broken: grouped writes only run once");
+        transaction.execute_batch(SAVEPOINT)?;
+        let changes = changes::savepoint();
+        // Each write gets the whole statement timeout to itself, so that a slow one
+        // doesn't use it up for the rest of the batch.
+        let timed_out = Arc::new(AtomicBool::new(false));
+        set_deadline(
+            transaction,
+            statement_timeout.map(|timeout| (timeout, Arc::clone(&timed_out))),
+        );
+        let (result, callbacks) =
+            callbacks::collect(|| catch_unwind(AssertUnwindSafe(|| f(transaction))));
+        set_deadline(transaction, None);
+        self.callbacks = callbacks;
+        let rollback = || {
+            if let Some(changes) = changes {
+                changes.rollback();
+            }
+            transaction.execute_batch(ROLLBACK)
+        };
+        let delivery = match result {
+            Ok(Ok(value)) => {
+                transaction.execute_batch(RELEASE)?;
+                Delivery::Finished(Ok(value))
+            }
+            Ok(Err(e)) => {
+                rollback()?;
+                Delivery::Finished(Err(e))
+            }
+            Err(panic) => {
+                rollback()?;
+                Delivery::Panicked(panic)
+            }
+        };
+        self.delivery = Some(delivery);
+        Ok(timed_out.load(Ordering::Acquire))
+    }
+
+    fn callbacks(&mut self, committed: bool) -> Vec<Callback> {
+        let callbacks = std::mem::take(&mut self.callbacks);
+        match self.delivery {
+            // A write which failed was rolled back to its savepoint, even if the rest committed.
+            Some(Delivery::Finished(ref result)) => callbacks.finish(committed && result.is_ok()),
+            // As with writes which aren't grouped, nothing is run after a panic.
+            _ => Vec::new(),
+        }
+    }
+
+    fn finish(self: Box<Self>, outcome: Result<(), Arc<Error>>) {
+        let delivery = match (self.delivery, outcome) {
+            // Anything that didn't make it into a committed transaction failed
+            // along with the transaction.
+            (None | Some(Delivery::Finished(Ok(_))), Err(e)) => {
+                Delivery::Failed(Error::GroupCommit(e))
+            }
+            (Some(delivery), _) => delivery,
+            (None, Ok(())) => unreachable!("grouped writes are always run before committing"),
+        };
+        // The caller may have gone away, in which case nobody cares about the result.
+        let _ = self.sender.send(delivery);
+    }
+}
+
+/// Run every write in the batch inside a single transaction, then commit it.
+fn commit_batch(
+    connection: &mut PooledConnection<ConnectionManager>,
+    batch: &mut [Box<dyn PendingWrite>],
+    statement_timeout: Option<Duration>,
+    stats: &Arc<StatsRecorder>,
+) -> Result<()> {
+    let transaction = trace::begin()
+        .in_scope(|| connection.transaction_with_behavior(TransactionBehavior::Immediate))
+        .map_err(Error::TransactionBegin)?;
+    let _timer = stats.time_transaction();
+    for write in batch.iter_mut() {
+        match write.run(&transaction, statement_timeout) {
+            Ok(timed_out) => {
+                if timed_out {
+                    stats.record_statement_timeout();
+                }
+            }
+            Err(e) => {
+                trace::rollback().in_scope(|| drop(transaction));
+                return Err(e.into());
+            }
+        }
+    }
+    trace::commit().in_scope(|| transaction.commit())?;
+    Ok(())
+}
+
+/// Pull writes off the queue in batches and commit each batch in one transaction.
+#[allow(clippy::too_many_arguments)]
+async fn drain<DB: 'static>(
+    name: &'static str,
+    mut receiver: mpsc::UnboundedReceiver<Box<dyn PendingWrite>>,
+    config: GroupCommitConfig,
+    connect_timeout: Duration,
+    statement_timeout: Option<Duration>,
+    writer_queue: WriterQueue,
+    writer: SharedPool,
+    stats: Arc<StatsRecorder>,
+    changes: Arc<ChangeNotifier>,
+) {
+    let max_batch_size = config.max_batch_size.max(1);
+    let max_linger = Duration::from_millis(config.max_linger);
+    while let Some(first) = receiver.recv().await {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + max_linger;
+        while batch.len() < max_batch_size {
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(write)) => batch.push(write),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        // The batch waits for the writer as the most important write in it would.
+        let authorization = batch
+            .iter()
+            .map(|write| write.authorization())
+            .max_by_key(|authorization| writer_queue.priority(authorization))
+            .cloned()
+            .expect("internal invariant broken: batches are never empty");
+        let outcome = match ConnectionPool::<DB>::get_conn_inner::<ConnectionHolder>(
+            connect_timeout,
+            None,
+            writer_queue.acquire(&authorization),
+            &writer,
+            &stats,
+            Some(&changes),
+            trace::connection(name, "writer", Some(&authorization)),
+        )
+        .await
+        {
+            Ok(holder) => {
+                // Statement timeouts apply to each write, rather than to the whole batch.
+                let run_stats = Arc::clone(&stats);
+                let (returned, outcome) = holder
+                    .run(move |connection| {
+                        let outcome =
+                            commit_batch(connection, &mut batch, statement_timeout, &run_stats);
+                        (batch, outcome)
+                    })
+                    .await
+                    .expect("internal invariant broken: runs without a statement timeout never time out");
+                batch = returned;
+                for write in &mut batch {
+                    holder.defer(write.callbacks(outcome.is_ok()));
+                }
+                outcome
+            }
+            Err(e) => Err(e),
+        };
+
+        // The writer has been released by now, so callers can carry on.
+        let outcome = outcome.map_err(Arc::new);
+        for write in batch {
+            write.finish(outcome.clone());
+        }
+    }
+
+    run_blocking(move || drop(writer)).await;
+}
+
+type Sender = mpsc::UnboundedSender<Box<dyn PendingWrite>>;
+
+/// Queue which coalesces concurrent writes into a single transaction.
+#[derive(Clone)]
+pub struct GroupCommitQueue {
+    /// Shared between clones of the pool, and taken once the pool shuts down.
+    sender: Arc<Mutex<Option<Sender>>>,
+}
+
+impl GroupCommitQueue {
+    /// Spawn the task which drains the queue against the given writer.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn<DB: 'static>(
+        name: &'static str,
+        config: &GroupCommitConfig,
+        connect_timeout: Duration,
+        statement_timeout: Option<Duration>,
+        writer_queue: WriterQueue,
+        writer: SharedPool,
+        stats: Arc<StatsRecorder>,
+        changes: Arc<ChangeNotifier>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(drain::<DB>(
+            name,
+            receiver,
+            config.clone(),
+            connect_timeout,
+            statement_timeout,
+            writer_queue,
+            writer,
+            stats,
+            changes,
+        ));
+        Self {
+            sender: Arc::new(Mutex::new(Some(sender))),
+        }
+    }
+
+    fn sender(&self) -> MutexGuard<'_, Option<Sender>> {
+        self.sender
+            .lock()
+            .expect("internal invariant broken: group commit sender is never poisoned")
+    }
+
+    /// Stop taking writes. Those already queued are still run (or failed) as usual.
+    pub fn close(&self) {
+        self.sender().take();
+    }
+
+    /// Queue the provided function and wait for the transaction it was grouped into to finish.
+    pub async fn submit<F, R, E>(
+        &self,
+        authorization: WriteAuthorization,
+        f: F,
+    ) -> Result<Result<R, E>>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let write = GroupedWrite {
+            authorization,
+            f: Some(f),
+            delivery: None,
+            callbacks: Callbacks::default(),
+            sender,
+        };
+        match self
+            .sender()
+            .as_ref()
+            .map(|sender| sender.send(Box::new(write)))
+        {
+            Some(Ok(())) => {}
+            None => return Err(Error::ShuttingDown),
+            // The task draining the queue has gone away, e.g. as the runtime is shutting down.
+            Some(Err(_)) => return Err(Error::GroupCommitStopped),
+        }
+        // Likewise if the task went away with the write still queued.
+        match receiver.await.map_err(|_| Error::GroupCommitStopped)? {
+            Delivery::Finished(result) => Ok(result),
+            Delivery::Panicked(panic) => resume_unwind(panic),
+            Delivery::Failed(e) => Err(e),
+        }
+    }
+}
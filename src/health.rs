@@ -0,0 +1,157 @@
+use crate::{migration::migration_version, ConnectionPool, WriteAuthorization};
+
+use std::{future::Future, marker::PhantomData, time::Duration};
+
+use rocket::{
+    data::Data,
+    http::Status,
+    route::{Handler, Outcome},
+    serde::json::Json,
+    Request,
+};
+use rusqlite::Connection;
+use serde::Serialize;
+use tokio::time::{timeout, Instant};
+
+/// Outcome of a single check.
+#[derive(Serialize, Debug)]
+pub struct CheckReport {
+    /// Whether the check passed.
+    pub ok: bool,
+    /// How long the check took, in milliseconds.
+    pub elapsed_ms: u128,
+    /// Why the check failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of checking the health of a database.
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+    /// The name of the database.
+    pub database: &'static str,
+    /// Whether all checks passed.
+    pub healthy: bool,
+    /// Check of a read connection.
+    pub reader: CheckReport,
+    /// Check of the writer, if configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writer: Option<CheckReport>,
+    /// The current migration version of the database, if it could be read.
+    pub migration_version: Option<i64>,
+}
+
+/// Run the given check, failing it if it takes longer than `deadline`.
+async fn check<T>(
+    deadline: Duration,
+    f: impl Future<Output = Result<T, String>> + Send,
+) -> (CheckReport, Option<T>) {
+    let started = Instant::now();
+    let (value, error) = match timeout(deadline, f).await {
+        Ok(Ok(value)) => (Some(value), None),
+        Ok(Err(e)) => (None, Some(e)),
+        Err(_) => (None, Some("timed out".to_owned())),
+    };
+    let report = CheckReport {
+        ok: error.is_none(),
+        elapsed_ms: started.elapsed().as_millis(),
+        error,
+    };
+    (report, value)
+}
+
+/// Checks run against a read connection, returning the migration version.
+fn check_reader(connection: &Connection, quick_check: bool) -> Result<i64, String> {
+    connection
+        .query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+        .map_err(|e| e.to_string())?;
+    if quick_check {
+        let result: String = connection
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if result != "ok" {
+            return Err(format!("quick_check failed: {result}"));
+        }
+    }
+    migration_version(connection).map_err(|e| e.to_string())
+}
+
+/// Check the health of the given pool.
+pub async fn check_health<DB: 'static>(pool: &ConnectionPool<DB>) -> HealthReport {
+    let config = pool.health_config();
+    let deadline = Duration::from_millis(config.timeout);
+    let quick_check = config.quick_check;
+
+    let (reader, migration_version) = check(deadline, async {
+        pool.connect_and_read(move |connection| check_reader(connection, quick_check))
+            .await
+            .map_err(|e| e.to_string())?
+    })
+    .await;
+
+    let writer = if config.check_writer {
+        let (writer, _) = check(deadline, async {
+            pool.connect_and_write(
+                WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest,
+                |transaction| {
+                    transaction
+                        .query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+                        .map_err(|e| e.to_string())
+                },
+            )
+            .await
+            .map_err(|e| e.to_string())?
+        })
+        .await;
+        Some(writer)
+    } else {
+        None
+    };
+
+    HealthReport {
+        database: pool.name(),
+        healthy: reader.ok && writer.as_ref().is_none_or(|writer| writer.ok),
+        reader,
+        writer,
+        migration_version,
+    }
+}
+
+/// Route handler which reports the health of a database pool.
+pub struct HealthHandler<DB> {
+    _marker: PhantomData<fn() -> DB>,
+}
+
+impl<DB> HealthHandler<DB> {
+    pub const fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<DB> Clone for HealthHandler<DB> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl<DB: 'static> Handler for HealthHandler<DB> {
+    async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> Outcome<'r> {
+        let Some(pool) = ConnectionPool::<DB>::get_pool(request.rocket()) else {
+            rocket::error!(
+                "Missing database fairing for `{}`",
+                std::any::type_name::<DB>()
+            );
+            return Outcome::Error(Status::InternalServerError);
+        };
+        let report = check_health(pool).await;
+        let status = if report.healthy {
+            Status::Ok
+        } else {
+            Status::ServiceUnavailable
+        };
+        Outcome::from(request, (status, Json(report)))
+    }
+}
@@ -1,29 +1,227 @@
-use std::sync::Arc;
+use crate::{
+    callbacks::{self, Callback},
+    changes::ChangeNotifier,
+    manager::ConnectionManager,
+    stats::StatsRecorder,
+    trace::Span,
+    util::run_blocking,
+    Error,
+};
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use r2d2::PooledConnection;
-use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, InterruptHandle};
 use tokio::sync::{Mutex, OwnedSemaphorePermit};
 
+/// Where a run is up to, shared between the future waiting for it and the
+/// blocking thread running it.
+#[derive(Default)]
+struct RunState {
+    /// Whether the function is running against the connection.
+    started: bool,
+    /// Whether the future waiting for the result was dropped.
+    cancelled: bool,
+}
+
+type SharedRunState = Arc<std::sync::Mutex<RunState>>;
+
+fn lock(state: &SharedRunState) -> std::sync::MutexGuard<'_, RunState> {
+    state
+        .lock()
+        .expect("internal invariant broken: run states are never poisoned")
+}
+
+/// Interrupts the query running on a connection if dropped while armed, i.e. when
+/// the future waiting for the query is dropped before the query finishes. Only
+/// this run's own function is interrupted, never whatever runs on the connection
+/// before or after it.
+struct InterruptOnDrop<'a> {
+    interrupt: &'a InterruptHandle,
+    state: SharedRunState,
+    armed: bool,
+}
+
+impl Drop for InterruptOnDrop<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            let mut state = lock(&self.state);
+            state.cancelled = true;
+            if state.started {
+                self.interrupt.interrupt();
+            }
+        }
+    }
+}
+
+/// Marks the function as no longer running once dropped, even if it panics.
+struct Started<'a> {
+    state: &'a SharedRunState,
+}
+
+impl Drop for Started<'_> {
+    fn drop(&mut self) {
+        lock(self.state).started = false;
+    }
+}
+
+/// How many virtual machine instructions are run between checks of the statement deadline.
+const PROGRESS_INTERVAL: i32 = 1000;
+
+/// Abort queries running on the connection once the given amount of time has passed
+/// from now, flagging that they were aborted. If there is no timeout, queries are never
+/// aborted.
+pub fn set_deadline(connection: &Connection, timeout: Option<(Duration, Arc<AtomicBool>)>) {
+    match timeout {
+        Some((timeout, timed_out)) => {
+            let deadline = Instant::now() + timeout;
+            connection.progress_handler(
+                PROGRESS_INTERVAL,
+                Some(move || {
+                    let expired = Instant::now() >= deadline;
+                    if expired {
+                        timed_out.store(true, Ordering::Release);
+                    }
+                    expired
+                }),
+            );
+        }
+        None => connection.progress_handler(PROGRESS_INTERVAL, None::<fn() -> bool>),
+    }
+}
+
 /// A holder for a connection that will be released when dropped.
 pub struct ConnectionHolder {
-    pub(crate) connection: Arc<Mutex<Option<PooledConnection<SqliteConnectionManager>>>>,
+    pub(crate) connection: Arc<Mutex<Option<PooledConnection<ConnectionManager>>>>,
+    pub(crate) interrupt: InterruptHandle,
+    pub(crate) statement_timeout: Option<Duration>,
     pub(crate) permit: Option<OwnedSemaphorePermit>,
+    pub(crate) stats: Arc<StatsRecorder>,
+    /// Where to publish the changes committed on the connection, if it is the writer.
+    pub(crate) changes: Option<Arc<ChangeNotifier>>,
+    /// Callbacks to spawn once the connection has been released.
+    pub(crate) callbacks: std::sync::Mutex<Vec<Callback>>,
+    pub(crate) acquired_at: Instant,
+    /// Span covering the time the connection is held for, which everything run on it is nested under.
+    pub(crate) span: Span,
 }
 
 impl ConnectionHolder {
-    /// Run the provided function against the connection.
+    /// Run the provided function against the connection on the blocking thread pool,
+    /// aborting its queries if they run for longer than the statement timeout.
     #[inline]
-    pub async fn run<F, R>(&self, f: F) -> R
+    pub async fn run<F, R>(&self, f: F) -> Result<R, Error>
     where
-        F: FnOnce(&mut PooledConnection<SqliteConnectionManager>) -> R + Send,
-        R: Send,
+        F: FnOnce(&mut PooledConnection<ConnectionManager>) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        let connection = Arc::clone(&self.connection);
-        let mut connection = connection.lock_owned().await;
-        let conn = connection
-            .as_mut()
-            .expect("internal invariant broken: self.connection is Some");
-        f(conn)
+        self.run_with_statement_timeout(self.statement_timeout, f)
+            .await
+    }
+
+    /// Run the provided function against the connection on the blocking thread pool,
+    /// aborting its queries if they run for longer than the given statement timeout.
+    /// If they were aborted, [`Error::StatementTimeout`] is returned instead of the
+    /// function's result, unless the function went on to commit a transaction anyway.
+    pub async fn run_with_statement_timeout<F, R>(
+        &self,
+        statement_timeout: Option<Duration>,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: FnOnce(&mut PooledConnection<ConnectionManager>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let timeout = statement_timeout.map(|timeout| (timeout, Arc::clone(&timed_out)));
+        let (value, committed) = self
+            .run_inner(move |connection| {
+                // This also clears any deadline left behind by a panic.
+                set_deadline(connection, timeout);
+                let value = f(connection);
+                set_deadline(connection, None);
+                value
+            })
+            .await;
+        if timed_out.load(Ordering::Acquire) {
+            self.stats.record_statement_timeout();
+            // The function may have carried on after the interrupt, in which case
+            // claiming that it failed would be a lie.
+            if !committed {
+                return Err(Error::StatementTimeout);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Run the provided function against the connection on the blocking thread pool,
+    /// returning whether it committed a transaction along with its result. If the
+    /// returned future is dropped before the function finishes, the query it is
+    /// running is interrupted.
+    async fn run_inner<F, R>(&self, f: F) -> (R, bool)
+    where
+        F: FnOnce(&mut PooledConnection<ConnectionManager>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let span = self.span.clone();
+        let changes = self.changes.clone();
+        let state = SharedRunState::default();
+        let mut guard = InterruptOnDrop {
+            interrupt: &self.interrupt,
+            state: Arc::clone(&state),
+            armed: true,
+        };
+
+        // Wait for the connection here rather than on the blocking thread, so that
+        // the thread isn't tied up in the meantime. Only the guard is moved to the
+        // thread, so the connection itself is never dropped from async code (see
+        // the comment in Drop).
+        let mut connection = Arc::clone(&self.connection).lock_owned().await;
+
+        // Run the (synchronous) closure on a blocking-safe thread so that
+        // long-running queries don't starve the async executor.
+        let result = run_blocking(move || {
+            let _entered = span.enter();
+            {
+                let mut state = lock(&state);
+                // Nobody is waiting for the result any more, so don't start the query.
+                if state.cancelled {
+                    return None;
+                }
+                state.started = true;
+            }
+            let _started = Started { state: &state };
+            let conn = connection
+                .as_mut()
+                .expect("internal invariant broken: self.connection is Some");
+            let Some(changes) = &changes else {
+                return Some((f(conn), false, Vec::new()));
+            };
+            let (value, callbacks) = changes.track(|| callbacks::collect(|| f(conn)));
+            let committed = changes.take_committed() == Some(true);
+            changes.publish(conn);
+            Some((value, committed, callbacks.finish(committed)))
+        })
+        .await;
+        guard.armed = false;
+        let (value, committed, callbacks) =
+            result.expect("internal invariant broken: only abandoned runs are skipped");
+        self.defer(callbacks);
+        (value, committed)
+    }
+
+    /// Spawn the given callbacks once the connection has been released.
+    pub(crate) fn defer(&self, callbacks: Vec<Callback>) {
+        self.callbacks
+            .lock()
+            .expect("internal invariant broken: callbacks are never poisoned")
+            .extend(callbacks);
     }
 }
 
@@ -36,6 +234,13 @@ impl Drop for ConnectionHolder {
         // wrappers do not or can not handle.
         let connection = Arc::clone(&self.connection);
         let permit = self.permit.take();
+        let stats = Arc::clone(&self.stats);
+        let acquired_at = self.acquired_at;
+        let callbacks = std::mem::take(
+            self.callbacks
+                .get_mut()
+                .expect("internal invariant broken: callbacks are never poisoned"),
+        );
 
         // Since connection can't be on the stack in an async fn during an
         // await, we have to spawn a new blocking-safe thread...
@@ -51,7 +256,9 @@ impl Drop for ConnectionHolder {
 
             // Explicitly dropping the permit here so that it's only
             // released after the connection is.
+            stats.record_release(acquired_at.elapsed());
             drop(permit);
+            callbacks::spawn(callbacks);
         });
     }
 }
@@ -0,0 +1,373 @@
+//! Durable background jobs, stored in the database they belong to.
+
+use crate::{
+    background::{self, Lease, AUTHORIZATION},
+    config::JobsConfig,
+    util::{unix_millis, unix_millis_after},
+    AuthorizedConnector, ConnectionPool, Database, Error, TransactionError,
+};
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use rocket::fairing::Fairing;
+use rusqlite::{named_params, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+
+const COMPONENT: &str = "jobs";
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE rocket_sqlite_rw_pool_jobs (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    -- pending, running (while leased) or dead
+    state TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- Milliseconds since the Unix epoch, as are the other times.
+    run_at INTEGER NOT NULL,
+    lease_until INTEGER,
+    last_error TEXT,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX rocket_sqlite_rw_pool_jobs_ready ON rocket_sqlite_rw_pool_jobs (state, run_at);"];
+
+const ENQUEUE: &str = "INSERT INTO rocket_sqlite_rw_pool_jobs (kind, payload, run_at, created_at)
+VALUES (:kind, :payload, :run_at, :now)";
+
+// Jobs whose lease ran out are claimed again, as whoever was running them went away.
+macro_rules! ready {
+    () => {
+        "kind IN (SELECT value FROM json_each(:kinds))
+        AND ((state = 'pending' AND run_at <= :now) OR (state = 'running' AND lease_until <= :now))"
+    };
+}
+
+// Checked on a reader first, so that the writer is only taken when there is something to claim.
+const ANY_READY: &str = concat!(
+    "SELECT EXISTS (SELECT 1 FROM rocket_sqlite_rw_pool_jobs WHERE ",
+    ready!(),
+    ")"
+);
+
+const CLAIM: &str = concat!(
+    "UPDATE rocket_sqlite_rw_pool_jobs
+SET state = 'running', attempts = attempts + 1, lease_until = :lease_until
+WHERE id IN (
+    SELECT id FROM rocket_sqlite_rw_pool_jobs
+    WHERE ",
+    ready!(),
+    "
+    ORDER BY run_at, id
+    LIMIT :limit
+)
+RETURNING id, kind, payload, attempts"
+);
+
+// Only the latest claim of a job may touch it, in case its lease ran out and
+// someone else claimed it in the meantime.
+const RENEW: &str = "UPDATE rocket_sqlite_rw_pool_jobs SET lease_until = :lease_until
+WHERE id = :id AND attempts = :attempt AND state = 'running'";
+
+const COMPLETE: &str = "DELETE FROM rocket_sqlite_rw_pool_jobs
+WHERE id = :id AND attempts = :attempt AND state = 'running'";
+
+const RETRY: &str = "UPDATE rocket_sqlite_rw_pool_jobs
+SET state = 'pending', run_at = :run_at, lease_until = NULL, last_error = :error
+WHERE id = :id AND attempts = :attempt AND state = 'running'";
+
+const DEAD_LETTER: &str = "UPDATE rocket_sqlite_rw_pool_jobs
+SET state = 'dead', lease_until = NULL, last_error = :error
+WHERE id = :id AND attempts = :attempt AND state = 'running'";
+
+/// Enqueue a job of the given kind, to run as soon as possible. As this is done inside
+/// a write transaction, the job only exists if the transaction commits.
+pub fn enqueue_job<T: Serialize>(
+    transaction: &Transaction,
+    kind: &str,
+    payload: &T,
+) -> Result<i64, rusqlite::Error> {
+    enqueue_job_at(transaction, kind, payload, SystemTime::now())
+}
+
+/// Enqueue a job of the given kind, to run no earlier than the given time.
+pub fn enqueue_job_at<T: Serialize>(
+    transaction: &Transaction,
+    kind: &str,
+    payload: &T,
+    run_at: SystemTime,
+) -> Result<i64, rusqlite::Error> {
+    let payload = serde_json::to_string(payload)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let mut statement = transaction.prepare_cached(ENQUEUE)?;
+    statement.execute(named_params! {
+        ":kind": kind,
+        ":payload": payload,
+        ":run_at": unix_millis(run_at),
+        ":now": unix_millis(SystemTime::now()),
+    })?;
+    Ok(transaction.last_insert_rowid())
+}
+
+/// A job claimed by the worker.
+#[derive(Clone, Debug)]
+pub struct Job {
+    /// The ID the job was enqueued with.
+    pub id: i64,
+    /// The kind of job, which determines its handler.
+    pub kind: String,
+    /// Which attempt at running the job this is, starting from 1.
+    pub attempt: u32,
+    payload: String,
+}
+
+impl Job {
+    /// Deserialize the payload the job was enqueued with.
+    pub fn payload<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_str(&self.payload)
+    }
+}
+
+/// The future returned by a [`JobHandlerFn`]. If it fails, the job is retried later.
+pub type JobFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+
+/// Function to run jobs of a single kind. Jobs may be run more than once (e.g. if
+/// the worker running them goes away), so this should be idempotent.
+pub type JobHandlerFn<DB> = for<'a> fn(AuthorizedConnector<'a, DB>, Job) -> JobFuture<'a>;
+
+/// Wrapper for a [`JobHandlerFn`], along with the kind of job it handles.
+pub struct JobHandler<DB> {
+    pub kind: &'static str,
+    pub handler: JobHandlerFn<DB>,
+}
+
+impl<DB> JobHandler<DB> {
+    pub const fn new(kind: &'static str, handler: JobHandlerFn<DB>) -> Self {
+        Self { kind, handler }
+    }
+}
+
+impl<DB> Clone for JobHandler<DB> {
+    fn clone(&self) -> Self {
+        Self::new(self.kind, self.handler)
+    }
+}
+
+/// Runs the jobs enqueued in a database.
+struct Worker<DB> {
+    pool: ConnectionPool<DB>,
+    handlers: HashMap<&'static str, JobHandlerFn<DB>>,
+    /// The kinds of job there are handlers for, as a JSON array.
+    kinds: String,
+    config: JobsConfig,
+}
+
+impl<DB: 'static> Worker<DB> {
+    /// Claim up to the given number of jobs which are ready to run.
+    async fn claim(&self, limit: usize) -> Result<Vec<Job>, TransactionError<rusqlite::Error>> {
+        let kinds = self.kinds.clone();
+        let any_ready: bool = self
+            .pool
+            .connect_and_read(move |connection| {
+                connection.query_row(
+                    ANY_READY,
+                    named_params! {
+                        ":kinds": kinds,
+                        ":now": unix_millis(SystemTime::now()),
+                    },
+                    |row| row.get(0),
+                )
+            })
+            .await
+            .and_then(|result| result.map_err(Error::from))?;
+        if !any_ready {
+            return Ok(Vec::new());
+        }
+
+        let kinds = self.kinds.clone();
+        let lease = Duration::from_millis(self.config.lease);
+        self.pool
+            .try_connect_and_write(AUTHORIZATION, move |transaction| {
+                let mut statement = transaction.prepare_cached(CLAIM)?;
+                let jobs = statement
+                    .query_map(
+                        named_params! {
+                            ":kinds": kinds,
+                            ":now": unix_millis(SystemTime::now()),
+                            ":lease_until": unix_millis_after(lease),
+                            ":limit": i64::try_from(limit).unwrap_or(i64::MAX),
+                        },
+                        |row| {
+                            Ok(Job {
+                                id: row.get(0)?,
+                                kind: row.get(1)?,
+                                payload: row.get(2)?,
+                                attempt: row.get(3)?,
+                            })
+                        },
+                    )?
+                    .collect();
+                jobs
+            })
+            .await
+    }
+
+    /// The lease taken on the given job when it was claimed.
+    fn lease(&self, job: &Job) -> Lease {
+        Lease {
+            description: format!("job {} ({})", job.id, job.kind),
+            duration: Duration::from_millis(self.config.lease),
+            renew: RENEW,
+            key: vec![(":id", job.id.into()), (":attempt", job.attempt.into())],
+        }
+    }
+
+    /// Run the given job, then record how it went.
+    async fn process(self: Arc<Self>, job: Job, permit: OwnedSemaphorePermit) {
+        let lease = self.lease(&job);
+        let result = if job.attempt > self.config.max_attempts {
+            Err(anyhow!("its lease ran out on its last attempt"))
+        } else {
+            let handler = *self
+                .handlers
+                .get(job.kind.as_str())
+                .expect("internal invariant broken: only jobs with handlers are claimed");
+            let pool = self.pool.clone();
+            let claimed = job.clone();
+            lease
+                .run(&self.pool, async move {
+                    handler(pool.get().authorize(AUTHORIZATION), claimed).await
+                })
+                .await
+        };
+
+        let update = match result {
+            Ok(()) => lease.execute(&self.pool, COMPLETE, Vec::new()).await,
+            Err(e) if job.attempt >= self.config.max_attempts => {
+                rocket::error!(
+                    "job {} ({}) on {} failed for the last time, dead-lettering it: {:#}",
+                    job.id,
+                    job.kind,
+                    self.pool.name(),
+                    e
+                );
+                lease
+                    .execute(
+                        &self.pool,
+                        DEAD_LETTER,
+                        vec![(":error", format!("{e:#}").into())],
+                    )
+                    .await
+            }
+            Err(e) => {
+                rocket::warn!(
+                    "job {} ({}) on {} failed on attempt {}, retrying: {:#}",
+                    job.id,
+                    job.kind,
+                    self.pool.name(),
+                    job.attempt,
+                    e
+                );
+                let backoff = Duration::from_millis(self.config.initial_backoff)
+                    .saturating_mul(2_u32.saturating_pow(job.attempt.saturating_sub(1)))
+                    .min(Duration::from_millis(self.config.max_backoff));
+                let run_at = unix_millis_after(backoff);
+                lease
+                    .execute(
+                        &self.pool,
+                        RETRY,
+                        vec![
+                            (":run_at", run_at.into()),
+                            (":error", format!("{e:#}").into()),
+                        ],
+                    )
+                    .await
+            }
+        };
+        // If this fails, the job is retried once its lease runs out.
+        if let Err(e) = update {
+            rocket::error!(
+                "failed to record the outcome of job {} on {}: {}",
+                job.id,
+                self.pool.name(),
+                e
+            );
+        }
+        drop(permit);
+    }
+
+    /// Claim and run jobs until told to stop.
+    async fn run(self: Arc<Self>, mut stop: watch::Receiver<bool>) {
+        let slots = Arc::new(Semaphore::new(self.config.concurrency.max(1)));
+        let poll_interval = Duration::from_millis(self.config.poll_interval);
+        loop {
+            let available = slots.available_permits();
+            if available > 0 {
+                match self.claim(available).await {
+                    Ok(jobs) => {
+                        for job in jobs {
+                            let permit = Arc::clone(&slots)
+                                .try_acquire_owned()
+                                .expect("internal invariant broken: only free slots are claimed");
+                            tokio::spawn(Arc::clone(&self).process(job, permit));
+                        }
+                    }
+                    Err(TransactionError::Database(Error::ShuttingDown)) => break,
+                    Err(e) => {
+                        rocket::error!("failed to claim jobs on {}: {}", self.pool.name(), e);
+                    }
+                }
+            }
+            tokio::select! {
+                _ = stop.changed() => break,
+                () = tokio::time::sleep(poll_interval) => {}
+            }
+        }
+    }
+}
+
+/// The background job queue for a database.
+pub struct JobQueue<DB> {
+    _marker: std::marker::PhantomData<fn() -> DB>,
+}
+
+impl<DB: Database> JobQueue<DB> {
+    /// Fairing which creates the job table, and runs a worker for the jobs with the given
+    /// handlers while rocket is running. The database's own fairing must also be attached.
+    pub fn fairing(handlers: Vec<JobHandler<DB>>) -> impl Fairing {
+        background::fairing(
+            "Job Queue",
+            COMPONENT,
+            MIGRATIONS,
+            move |rocket, pool: ConnectionPool<DB>, config| async move {
+                let kinds = serde_json::to_string(
+                    &handlers
+                        .iter()
+                        .map(|handler| handler.kind)
+                        .collect::<Vec<_>>(),
+                )
+                .expect("internal invariant broken: strings always serialize");
+                let worker = Arc::new(Worker {
+                    pool,
+                    handlers: handlers
+                        .into_iter()
+                        .map(|handler| (handler.kind, handler.handler))
+                        .collect(),
+                    kinds,
+                    config: config.jobs,
+                });
+                Ok(background::spawn_worker(
+                    rocket,
+                    "Job Queue Worker",
+                    move |stop| worker.run(stop),
+                ))
+            },
+        )
+    }
+}
@@ -10,17 +10,36 @@
 
 mod auth;
 mod authorized_connector;
+mod background;
 mod batched;
+mod builtin_migrations;
+mod callbacks;
+mod change_stream;
+mod changes;
 mod config;
 mod connector;
+mod database;
 mod error;
+mod group_commit;
+mod health;
 mod holder;
+mod jobs;
 mod macros;
+mod manager;
+mod metrics;
 mod migration;
 mod pool;
 mod pragmas;
+mod priority;
 mod query;
 mod read;
+mod savepoint;
+mod scheduler;
+mod slow_query;
+mod stats;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod trace;
 mod util;
 mod write;
 
@@ -30,10 +49,19 @@ pub use paste;
 pub use auth::WriteAuthorization;
 pub use authorized_connector::AuthorizedConnector;
 pub use batched::BatchedBulkValuesClause;
+pub use callbacks::TransactionCallbacks;
+pub use change_stream::ChangeStream;
+pub use changes::{Change, ChangeOperation};
 pub use connector::Connector;
-pub use error::Error;
+pub use database::Database;
+pub use error::{Error, TransactionError};
+pub use health::{CheckReport, HealthReport};
+pub use jobs::{enqueue_job, enqueue_job_at, Job, JobFuture, JobHandler, JobHandlerFn, JobQueue};
 pub use pool::{ConnectionPool, PoolInitializer, PoolInitializerFn};
 pub use query::*;
 pub use read::ReadConnection;
 pub use rust_embed;
+pub use savepoint::Nested;
+pub use scheduler::{Schedule, ScheduledTask, ScheduledTaskFn, ScheduledTaskFuture, Scheduler};
+pub use stats::{ConnectionStats, LatencyHistogram, PoolStats};
 pub use write::WriteConnection;
@@ -8,22 +8,23 @@ macro_rules! define_database {
         rocket_sqlite_rw_pool::paste::paste! {
             impl $struct_name {
                 pub fn fairing() -> impl rocket::fairing::Fairing {
+                    const FAIRING_NAME: &'static str = concat!($name, " Database Pool");
                     let initializers: Vec<_> = rocket_sqlite_rw_pool::inventory::iter::<
-                        rocket_sqlite_rw_pool::PoolInitializer<$struct_name>,
-                    >
-                        .map(rocket_sqlite_rw_pool::TypeErasedPoolInitializer::from)
+                        [<$struct_name _Initializer>],
+                    >()
+                        .map(rocket_sqlite_rw_pool::PoolInitializer::from)
                         .collect();
                     <rocket_sqlite_rw_pool::ConnectionPool<Self>>::fairing(
-                        "'#name' Database Pool",
+                        FAIRING_NAME,
                         $name,
                         initializers,
                     )
                 }
 
-                pub async fn get_one<'rocket, P: rocket::Phase>(
+                pub fn get_one<'rocket, P: rocket::Phase>(
                     rocket: &'rocket rocket::Rocket<P>,
                 ) -> Option<rocket_sqlite_rw_pool::Connector<'rocket, Self>> {
-                    <rocket_sqlite_rw_pool::ConnectionPool<Self>>::get_one(&rocket).await
+                    <rocket_sqlite_rw_pool::ConnectionPool<Self>>::get_one(&rocket)
                 }
 
                 pub fn pool<P: rocket::Phase>(
@@ -31,6 +32,44 @@ macro_rules! define_database {
                 ) -> Option<&rocket_sqlite_rw_pool::ConnectionPool<Self>> {
                     <rocket_sqlite_rw_pool::ConnectionPool<Self>>::get_pool(&rocket)
                 }
+
+                pub fn metrics_routes() -> Vec<rocket::Route> {
+                    <rocket_sqlite_rw_pool::ConnectionPool<Self>>::metrics_routes()
+                }
+
+                pub fn health_routes() -> Vec<rocket::Route> {
+                    <rocket_sqlite_rw_pool::ConnectionPool<Self>>::health_routes()
+                }
+
+                pub fn health_fairing(base: &'static str) -> impl rocket::fairing::Fairing {
+                    <rocket_sqlite_rw_pool::ConnectionPool<Self>>::health_fairing(base)
+                }
+
+                pub fn jobs_fairing() -> impl rocket::fairing::Fairing {
+                    let handlers: Vec<_> = rocket_sqlite_rw_pool::inventory::iter::<
+                        [<$struct_name _JobHandler>],
+                    >()
+                        .map(rocket_sqlite_rw_pool::JobHandler::from)
+                        .collect();
+                    rocket_sqlite_rw_pool::JobQueue::<Self>::fairing(handlers)
+                }
+
+                pub fn scheduler_fairing() -> impl rocket::fairing::Fairing {
+                    let tasks: Vec<_> = rocket_sqlite_rw_pool::inventory::iter::<
+                        [<$struct_name _ScheduledTask>],
+                    >()
+                        .map(rocket_sqlite_rw_pool::ScheduledTask::from)
+                        .collect();
+                    rocket_sqlite_rw_pool::Scheduler::<Self>::fairing(tasks)
+                }
+            }
+
+            impl rocket_sqlite_rw_pool::Database for $struct_name {
+                const NAME: &'static str = $name;
+
+                fn attach(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
+                    rocket.attach(Self::fairing())
+                }
             }
 
             pub struct [<$struct_name _Initializer>] {
@@ -45,7 +84,7 @@ macro_rules! define_database {
                 }
             }
 
-            impl From<&'static [<$struct_name _Initializer>]> for rocket_sqlite_rw_pool::TypeErasedPoolInitializer {
+            impl From<&'static [<$struct_name _Initializer>]> for rocket_sqlite_rw_pool::PoolInitializer {
                 fn from(initializer: &'static [<$struct_name _Initializer>]) -> Self {
                     Self {
                         initializer: initializer.initializer,
@@ -56,6 +95,57 @@ macro_rules! define_database {
             rocket_sqlite_rw_pool::inventory::collect!(
                 [<$struct_name _Initializer>]
             );
+
+            pub struct [<$struct_name _JobHandler>] {
+                handler: rocket_sqlite_rw_pool::JobHandler<$struct_name>
+            }
+
+            impl [<$struct_name _JobHandler>] {
+                pub const fn new(
+                    kind: &'static str,
+                    handler: rocket_sqlite_rw_pool::JobHandlerFn<$struct_name>,
+                ) -> Self {
+                    Self {
+                        handler: rocket_sqlite_rw_pool::JobHandler::new(kind, handler)
+                    }
+                }
+            }
+
+            impl From<&'static [<$struct_name _JobHandler>]> for rocket_sqlite_rw_pool::JobHandler<$struct_name> {
+                fn from(handler: &'static [<$struct_name _JobHandler>]) -> Self {
+                    handler.handler.clone()
+                }
+            }
+
+            rocket_sqlite_rw_pool::inventory::collect!(
+                [<$struct_name _JobHandler>]
+            );
+
+            pub struct [<$struct_name _ScheduledTask>] {
+                task: rocket_sqlite_rw_pool::ScheduledTask<$struct_name>
+            }
+
+            impl [<$struct_name _ScheduledTask>] {
+                pub const fn new(
+                    name: &'static str,
+                    schedule: rocket_sqlite_rw_pool::Schedule,
+                    task: rocket_sqlite_rw_pool::ScheduledTaskFn<$struct_name>,
+                ) -> Self {
+                    Self {
+                        task: rocket_sqlite_rw_pool::ScheduledTask::new(name, schedule, task)
+                    }
+                }
+            }
+
+            impl From<&'static [<$struct_name _ScheduledTask>]> for rocket_sqlite_rw_pool::ScheduledTask<$struct_name> {
+                fn from(task: &'static [<$struct_name _ScheduledTask>]) -> Self {
+                    task.task.clone()
+                }
+            }
+
+            rocket_sqlite_rw_pool::inventory::collect!(
+                [<$struct_name _ScheduledTask>]
+            );
         }
     };
 
@@ -105,6 +195,44 @@ macro_rules! define_database {
                 ) -> Option<&rocket_sqlite_rw_pool::ConnectionPool<Self>> {
                     <rocket_sqlite_rw_pool::ConnectionPool<Self>>::get_pool(&rocket)
                 }
+
+                pub fn metrics_routes() -> Vec<rocket::Route> {
+                    <rocket_sqlite_rw_pool::ConnectionPool<Self>>::metrics_routes()
+                }
+
+                pub fn health_routes() -> Vec<rocket::Route> {
+                    <rocket_sqlite_rw_pool::ConnectionPool<Self>>::health_routes()
+                }
+
+                pub fn health_fairing(base: &'static str) -> impl rocket::fairing::Fairing {
+                    <rocket_sqlite_rw_pool::ConnectionPool<Self>>::health_fairing(base)
+                }
+
+                pub fn jobs_fairing() -> impl rocket::fairing::Fairing {
+                    let handlers: Vec<_> = rocket_sqlite_rw_pool::inventory::iter::<
+                        [<$struct_name _JobHandler>],
+                    >()
+                        .map(rocket_sqlite_rw_pool::JobHandler::from)
+                        .collect();
+                    rocket_sqlite_rw_pool::JobQueue::<Self>::fairing(handlers)
+                }
+
+                pub fn scheduler_fairing() -> impl rocket::fairing::Fairing {
+                    let tasks: Vec<_> = rocket_sqlite_rw_pool::inventory::iter::<
+                        [<$struct_name _ScheduledTask>],
+                    >()
+                        .map(rocket_sqlite_rw_pool::ScheduledTask::from)
+                        .collect();
+                    rocket_sqlite_rw_pool::Scheduler::<Self>::fairing(tasks)
+                }
+            }
+
+            impl rocket_sqlite_rw_pool::Database for $struct_name {
+                const NAME: &'static str = $name;
+
+                fn attach(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
+                    rocket.attach(Self::fairing())
+                }
             }
 
             pub struct [<$struct_name _Initializer>] {
@@ -130,6 +258,57 @@ macro_rules! define_database {
             rocket_sqlite_rw_pool::inventory::collect!(
                 [<$struct_name _Initializer>]
             );
+
+            pub struct [<$struct_name _JobHandler>] {
+                handler: rocket_sqlite_rw_pool::JobHandler<$struct_name>
+            }
+
+            impl [<$struct_name _JobHandler>] {
+                pub const fn new(
+                    kind: &'static str,
+                    handler: rocket_sqlite_rw_pool::JobHandlerFn<$struct_name>,
+                ) -> Self {
+                    Self {
+                        handler: rocket_sqlite_rw_pool::JobHandler::new(kind, handler)
+                    }
+                }
+            }
+
+            impl From<&'static [<$struct_name _JobHandler>]> for rocket_sqlite_rw_pool::JobHandler<$struct_name> {
+                fn from(handler: &'static [<$struct_name _JobHandler>]) -> Self {
+                    handler.handler.clone()
+                }
+            }
+
+            rocket_sqlite_rw_pool::inventory::collect!(
+                [<$struct_name _JobHandler>]
+            );
+
+            pub struct [<$struct_name _ScheduledTask>] {
+                task: rocket_sqlite_rw_pool::ScheduledTask<$struct_name>
+            }
+
+            impl [<$struct_name _ScheduledTask>] {
+                pub const fn new(
+                    name: &'static str,
+                    schedule: rocket_sqlite_rw_pool::Schedule,
+                    task: rocket_sqlite_rw_pool::ScheduledTaskFn<$struct_name>,
+                ) -> Self {
+                    Self {
+                        task: rocket_sqlite_rw_pool::ScheduledTask::new(name, schedule, task)
+                    }
+                }
+            }
+
+            impl From<&'static [<$struct_name _ScheduledTask>]> for rocket_sqlite_rw_pool::ScheduledTask<$struct_name> {
+                fn from(task: &'static [<$struct_name _ScheduledTask>]) -> Self {
+                    task.task.clone()
+                }
+            }
+
+            rocket_sqlite_rw_pool::inventory::collect!(
+                [<$struct_name _ScheduledTask>]
+            );
         }
     };
 }
@@ -148,7 +327,10 @@ macro_rules! define_from_request_for_pool_holder {
                 request: &'r rocket::request::Request<'_>,
             ) -> rocket::request::Outcome<Self, Self::Error> {
                 match request.rocket().state::<$crate::ConnectionPool<DB>>() {
-                    Some(pool) => rocket::request::Outcome::Success($struct_name { pool }),
+                    Some(pool) => rocket::request::Outcome::Success($struct_name {
+                        pool,
+                        read_cache: Some(request.local_cache(Default::default)),
+                    }),
                     None => {
                         rocket::error!(
                             "Missing database fairing for `{}`",
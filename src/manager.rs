@@ -0,0 +1,45 @@
+use r2d2::ManageConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, Error};
+
+/// Wrapper around a [`SqliteConnectionManager`] which can check connections
+/// with a custom query before they are handed out.
+#[derive(Debug)]
+pub struct ConnectionManager {
+    inner: SqliteConnectionManager,
+    validation_query: Option<String>,
+}
+
+impl ConnectionManager {
+    pub const fn new(inner: SqliteConnectionManager, validation_query: Option<String>) -> Self {
+        Self {
+            inner,
+            validation_query,
+        }
+    }
+}
+
+impl ManageConnection for ConnectionManager {
+    type Connection = Connection;
+    type Error = Error;
+
+    fn connect(&self) -> Result<Connection, Error> {
+        self.inner.connect()
+    }
+
+    fn is_valid(&self, connection: &mut Connection) -> Result<(), Error> {
+        match &self.validation_query {
+            Some(query) => {
+                let mut statement = connection.prepare_cached(query)?;
+                let mut rows = statement.query([])?;
+                rows.next()?;
+                Ok(())
+            }
+            None => self.inner.is_valid(connection),
+        }
+    }
+
+    fn has_broken(&self, connection: &mut Connection) -> bool {
+        self.inner.has_broken(connection)
+    }
+}
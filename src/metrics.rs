@@ -0,0 +1,297 @@
+use crate::{migration::migration_version, ConnectionPool, ConnectionStats, LatencyHistogram};
+
+use std::{fmt::Write, marker::PhantomData};
+
+use rocket::{
+    data::Data,
+    http::{ContentType, Status},
+    route::{Handler, Outcome},
+    Request,
+};
+use rusqlite::Connection;
+
+/// Information about the database file itself, rather than the pool.
+struct DatabaseInfo {
+    wal_size: Option<u64>,
+    migration_version: i64,
+}
+
+impl DatabaseInfo {
+    fn read(connection: &Connection) -> Result<Self, rusqlite::Error> {
+        let file: String = connection.query_row(
+            "SELECT file FROM pragma_database_list WHERE name = 'main'",
+            [],
+            |row| row.get(0),
+        )?;
+        // In-memory and temporary databases have no file, and hence no WAL.
+        let wal_size = if file.is_empty() {
+            None
+        } else {
+            std::fs::metadata(format!("{file}-wal"))
+                .map(|metadata| metadata.len())
+                .ok()
+        };
+        let migration_version = migration_version(connection)?;
+        Ok(Self {
+            wal_size,
+            migration_version,
+        })
+    }
+}
+
+/// Escape a label value as required by the Prometheus text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Writes metrics in the Prometheus text exposition format.
+struct Writer<'a> {
+    out: String,
+    database: &'a str,
+}
+
+impl Writer<'_> {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {name} {help}");
+        let _ = writeln!(self.out, "# TYPE {name} {kind}");
+    }
+
+    fn sample(&mut self, name: &str, role: Option<&str>, value: impl std::fmt::Display) {
+        let _ = match role {
+            Some(role) => writeln!(
+                self.out,
+                "{name}{{database=\"{}\",role=\"{role}\"}} {value}",
+                self.database
+            ),
+            None => writeln!(self.out, "{name}{{database=\"{}\"}} {value}", self.database),
+        };
+    }
+
+    fn gauge<T: std::fmt::Display>(
+        &mut self,
+        name: &str,
+        help: &str,
+        stats: &[(&str, &ConnectionStats)],
+        value: impl Fn(&ConnectionStats) -> T,
+    ) {
+        self.header(name, "gauge", help);
+        for (role, stats) in stats {
+            self.sample(name, Some(role), value(stats));
+        }
+    }
+
+    fn counter(
+        &mut self,
+        name: &str,
+        help: &str,
+        stats: &[(&str, &ConnectionStats)],
+        value: impl Fn(&ConnectionStats) -> u64,
+    ) {
+        self.header(name, "counter", help);
+        for (role, stats) in stats {
+            self.sample(name, Some(role), value(stats));
+        }
+    }
+
+    fn histogram(
+        &mut self,
+        name: &str,
+        help: &str,
+        stats: &[(&str, &ConnectionStats)],
+        value: impl Fn(&ConnectionStats) -> &LatencyHistogram,
+    ) {
+        self.header(name, "histogram", help);
+        for (role, stats) in stats {
+            let histogram = value(stats);
+            let mut cumulative = 0;
+            for (bound, count) in &histogram.buckets {
+                cumulative += count;
+                let bound =
+                    bound.map_or_else(|| "+Inf".to_owned(), |b| b.as_secs_f64().to_string());
+                let _ = writeln!(
+                    self.out,
+                    "{name}_bucket{{database=\"{}\",role=\"{role}\",le=\"{bound}\"}} {cumulative}",
+                    self.database
+                );
+            }
+            self.sample(
+                &format!("{name}_sum"),
+                Some(role),
+                histogram.sum.as_secs_f64(),
+            );
+            self.sample(&format!("{name}_count"), Some(role), histogram.count);
+        }
+    }
+}
+
+/// Write the gauges describing the current state of each side of the pool.
+fn write_gauges(writer: &mut Writer<'_>, stats: &[(&str, &ConnectionStats)]) {
+    writer.gauge(
+        "sqlite_pool_max_connections",
+        "Maximum number of connections in the pool.",
+        stats,
+        |stats| stats.max_connections,
+    );
+    writer.gauge(
+        "sqlite_pool_connections",
+        "Number of open connections in the pool.",
+        stats,
+        |stats| stats.connections,
+    );
+    writer.gauge(
+        "sqlite_pool_idle_connections",
+        "Number of idle connections in the pool.",
+        stats,
+        |stats| stats.idle_connections,
+    );
+    writer.gauge(
+        "sqlite_pool_in_use_connections",
+        "Number of connections currently handed out.",
+        stats,
+        |stats| stats.in_use,
+    );
+    writer.gauge(
+        "sqlite_pool_waiting_tasks",
+        "Number of tasks waiting for a connection permit.",
+        stats,
+        |stats| stats.waiting,
+    );
+}
+
+/// Write the counters for each side of the pool.
+fn write_counters(writer: &mut Writer<'_>, stats: &[(&str, &ConnectionStats)]) {
+    writer.counter(
+        "sqlite_pool_acquired_total",
+        "Total number of connections handed out.",
+        stats,
+        |stats| stats.acquired,
+    );
+    writer.counter(
+        "sqlite_pool_permit_timeouts_total",
+        "Total number of timeouts waiting for a connection permit.",
+        stats,
+        |stats| stats.permit_timeouts,
+    );
+    writer.counter(
+        "sqlite_pool_checkout_failures_total",
+        "Total number of failures checking out a connection.",
+        stats,
+        |stats| stats.checkout_failures,
+    );
+    writer.counter(
+        "sqlite_pool_statement_timeouts_total",
+        "Total number of calls aborted for exceeding the statement timeout.",
+        stats,
+        |stats| stats.statement_timeouts,
+    );
+}
+
+/// Write the latency histograms for each side of the pool.
+fn write_histograms(writer: &mut Writer<'_>, stats: &[(&str, &ConnectionStats)]) {
+    writer.histogram(
+        "sqlite_pool_permit_wait_seconds",
+        "Time spent waiting for a connection permit.",
+        stats,
+        |stats| &stats.permit_wait,
+    );
+    writer.histogram(
+        "sqlite_pool_checkout_seconds",
+        "Time spent checking out a connection.",
+        stats,
+        |stats| &stats.checkout,
+    );
+    writer.histogram(
+        "sqlite_pool_connection_held_seconds",
+        "Time connections were held for before being released.",
+        stats,
+        |stats| &stats.held,
+    );
+    writer.histogram(
+        "sqlite_pool_transaction_seconds",
+        "Time transactions were open for, from beginning until being committed or rolled back.",
+        stats,
+        |stats| &stats.transactions,
+    );
+}
+
+/// Write the metrics describing the database file.
+fn write_database_info(writer: &mut Writer<'_>, info: &DatabaseInfo) {
+    if let Some(wal_size) = info.wal_size {
+        writer.header(
+            "sqlite_wal_size_bytes",
+            "gauge",
+            "Size of the write-ahead log.",
+        );
+        writer.sample("sqlite_wal_size_bytes", None, wal_size);
+    }
+    writer.header(
+        "sqlite_migration_version",
+        "gauge",
+        "Current migration version of the database.",
+    );
+    writer.sample("sqlite_migration_version", None, info.migration_version);
+}
+
+/// Render the metrics for the given pool in the Prometheus text exposition format.
+pub async fn render<DB: 'static>(pool: &ConnectionPool<DB>) -> String {
+    let stats = pool.stats();
+    let info = match pool.connect_and_read(DatabaseInfo::read).await {
+        Ok(Ok(info)) => Some(info),
+        Ok(Err(e)) => {
+            rocket::error!("failed to read database info for metrics: {}", e);
+            None
+        }
+        Err(_) => None,
+    };
+
+    let database = escape(pool.name());
+    let mut writer = Writer {
+        out: String::new(),
+        database: &database,
+    };
+    let stats = [("reader", &stats.readers), ("writer", &stats.writer)];
+    write_gauges(&mut writer, &stats);
+    write_counters(&mut writer, &stats);
+    write_histograms(&mut writer, &stats);
+    if let Some(info) = info {
+        write_database_info(&mut writer, &info);
+    }
+    writer.out
+}
+
+/// Route handler which renders the metrics for a database pool.
+pub struct MetricsHandler<DB> {
+    _marker: PhantomData<fn() -> DB>,
+}
+
+impl<DB> MetricsHandler<DB> {
+    pub const fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<DB> Clone for MetricsHandler<DB> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl<DB: 'static> Handler for MetricsHandler<DB> {
+    async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> Outcome<'r> {
+        let Some(pool) = ConnectionPool::<DB>::get_pool(request.rocket()) else {
+            rocket::error!(
+                "Missing database fairing for `{}`",
+                std::any::type_name::<DB>()
+            );
+            return Outcome::Error(Status::InternalServerError);
+        };
+        let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+        Outcome::from(request, (content_type, render(pool).await))
+    }
+}
@@ -32,6 +32,12 @@ impl MigrationSourceType {
     }
 }
 
+/// The current migration version of the database.
+pub fn migration_version(connection: &Connection) -> Result<i64, rusqlite::Error> {
+    // rusqlite_migration tracks the version in user_version.
+    connection.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
 #[allow(clippy::too_many_lines)]
 pub fn run_migrations<T: RustEmbed>(
     db_name: &'static str,
@@ -44,7 +50,7 @@ pub fn run_migrations<T: RustEmbed>(
         if parts.len() != 2
             || !std::path::Path::new(parts[1])
                 .extension()
-                .map_or(false, |ext| ext.eq_ignore_ascii_case("sql"))
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("sql"))
         {
             return None;
         }
@@ -1,20 +1,46 @@
 use crate::{
-    config::Config, holder::ConnectionHolder, migration::run_migrations, util::run_blocking,
-    Connector, Error, ReadConnection, WriteAuthorization, WriteConnection,
+    changes::ChangeNotifier,
+    config::{Config, HealthCheckConfig, RetryConfig},
+    group_commit::GroupCommitQueue,
+    health::{check_health, HealthHandler},
+    holder::ConnectionHolder,
+    manager::ConnectionManager,
+    metrics::MetricsHandler,
+    migration::run_migrations,
+    pragmas::quote_identifier,
+    priority::WriterQueue,
+    read::ReadCache,
+    slow_query,
+    stats::StatsRecorder,
+    trace::{self, Instrument, Span},
+    util::run_blocking,
+    write::Retry,
+    Change, Connector, Error, HealthReport, PoolStats, ReadConnection, TransactionError,
+    WriteAuthorization, WriteConnection,
 };
 
-use std::{marker::PhantomData, sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    marker::PhantomData,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rocket::{
     fairing::{AdHoc, Fairing},
-    Build, Phase, Rocket,
+    http::Method,
+    Build, Phase, Rocket, Route,
 };
 use rusqlite::{Connection, OpenFlags, Transaction};
 use rust_embed::RustEmbed;
 use tokio::{
-    sync::{Mutex, Semaphore},
+    sync::{broadcast, Mutex, OwnedSemaphorePermit, Semaphore},
     time::timeout,
 };
 
@@ -35,18 +61,88 @@ impl PoolInitializer {
     }
 }
 
+/// An r2d2 pool shared between clones of a [`ConnectionPool`]. This is an `Option`
+/// so that shutting down can close it for all of them, and so that we can drop it
+/// in a `spawn_blocking`.
+pub type SharedPool = Arc<RwLock<Option<Pool<ConnectionManager>>>>;
+
+/// Get the pool out of a [`SharedPool`], unless it has been shut down.
+pub fn current_pool(pool: &SharedPool) -> Option<Pool<ConnectionManager>> {
+    pool.read()
+        .expect("internal invariant broken: pool lock is never poisoned")
+        .clone()
+}
+
+/// Take the pool out of a [`SharedPool`] if nothing else is using it.
+fn take_if_unique(pool: &mut SharedPool) -> Option<Pool<ConnectionManager>> {
+    Arc::get_mut(pool)
+        .and_then(|pool| pool.get_mut().ok())
+        .and_then(Option::take)
+}
+
+/// Checkpoint (and truncate) the WAL, and let `SQLite` optimize the database.
+fn checkpoint(connection: &Connection) -> Result<(), rusqlite::Error> {
+    let busy: i64 =
+        connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+    if busy != 0 {
+        rocket::warn!("WAL checkpoint could not complete as the database is busy");
+    }
+    connection.execute_batch("PRAGMA optimize")
+}
+
+/// URL which makes a pool use an in-memory database.
+const MEMORY_URL: &str = ":memory:";
+
+/// Create a unique URL for an in-memory database. This uses the memdb VFS, which
+/// shares databases whose name starts with a `/` between connections in the same process.
+fn memory_url(name: &str) -> String {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "file:/{name}-{}?vfs=memdb",
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The URI to attach a database with, making it read-only if requested.
+fn attach_uri(url: &str, read_only: bool) -> String {
+    if !read_only {
+        url.to_owned()
+    } else if url.starts_with("file:") {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        format!("{url}{separator}mode=ro")
+    } else {
+        let path = url
+            .replace('%', "%25")
+            .replace('?', "%3f")
+            .replace('#', "%23");
+        format!("file:{path}?mode=ro")
+    }
+}
+
+/// Flags for opening the writer.
+fn writer_flags() -> OpenFlags {
+    OpenFlags::SQLITE_OPEN_URI
+        | OpenFlags::SQLITE_OPEN_NO_MUTEX
+        | OpenFlags::SQLITE_OPEN_READ_WRITE
+        | OpenFlags::SQLITE_OPEN_CREATE
+}
+
 /// Create a connection pool with the given configuration.
 fn create_pool(
+    name: &'static str,
     config: &Config,
+    url: &str,
     is_write: bool,
+    changes: Option<&Arc<ChangeNotifier>>,
     initializers: Vec<PoolInitializer>,
-) -> Result<Pool<SqliteConnectionManager>> {
-    let mut flags = OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
-    if is_write {
-        flags = flags | OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+) -> Result<Pool<ConnectionManager>> {
+    let flags = if is_write {
+        writer_flags()
     } else {
-        flags |= OpenFlags::SQLITE_OPEN_READ_ONLY;
-    }
+        OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_READ_ONLY
+    };
     let max_size = if is_write {
         1
     } else {
@@ -57,9 +153,32 @@ fn create_pool(
     } else {
         config.min_read_connections
     };
+    let connection_config = if is_write {
+        &config.writer
+    } else {
+        &config.readers
+    };
     let pragmas = config.pragmas.clone();
-    let busy_timeout = config.busy_timeout;
-    let manager = SqliteConnectionManager::file(&config.url)
+    let slow_query_log = config.slow_query_log.clone();
+    let changes = changes.cloned();
+    let attach: Vec<_> = config
+        .attach
+        .iter()
+        .map(|(schema, database)| {
+            let read_only = database.read_only || !is_write;
+            (
+                schema.clone(),
+                attach_uri(&database.url, read_only),
+                read_only,
+            )
+        })
+        .collect();
+    // In-memory databases don't support WAL, so readers have to wait for the
+    // writer to commit.
+    let busy_timeout = config
+        .busy_timeout
+        .or_else(|| (config.url == MEMORY_URL).then_some(config.connect_timeout));
+    let manager = SqliteConnectionManager::file(url)
         .with_flags(flags)
         .with_init(move |connection| {
             if let Some(timeout) = busy_timeout {
@@ -69,40 +188,83 @@ fn create_pool(
                 return Err(rusqlite::Error::InvalidQuery);
             }
             pragmas.set(connection)?;
+            if let Some(slow_query_log) = &slow_query_log {
+                slow_query::install(connection, name, is_write, slow_query_log)?;
+            }
+            if let Some(changes) = &changes {
+                changes.install(connection);
+            }
+            // Attached after the pragmas, as some of them would otherwise try to
+            // change read-only attached databases too.
+            for (schema, uri, read_only) in &attach {
+                connection.execute(
+                    &format!("ATTACH DATABASE ?1 AS {}", quote_identifier(schema)),
+                    [uri],
+                )?;
+                if !read_only {
+                    pragmas.set_attached(connection, schema)?;
+                }
+            }
             for initializer in &initializers {
                 (initializer.initializer)(connection)?;
             }
             Ok(())
         });
-    let pool = Pool::builder()
+    let manager = ConnectionManager::new(manager, connection_config.validation_query.clone());
+    let mut builder = Pool::builder()
         .max_size(max_size)
         .min_idle(min_idle)
         .idle_timeout(config.idle_timeout.map(Duration::from_secs))
         .connection_timeout(Duration::from_secs(config.connect_timeout))
-        .build(manager)
-        .map_err(Error::PoolCreation)?;
+        .test_on_check_out(true);
+    if let Some(max_lifetime) = connection_config.max_lifetime {
+        builder =
+            builder.max_lifetime((max_lifetime > 0).then(|| Duration::from_secs(max_lifetime)));
+    }
+    let pool = builder.build(manager).map_err(Error::PoolCreation)?;
     Ok(pool)
 }
 
 /// Pool of database connections.
 pub struct ConnectionPool<DB> {
+    name: &'static str,
     connect_timeout: Duration,
-    // This is an 'Option' so that we can drop the pool in a 'spawn_blocking'.
-    writer: Option<Pool<SqliteConnectionManager>>,
-    writer_semaphore: Arc<Semaphore>,
-    readers: Option<Pool<SqliteConnectionManager>>,
+    statement_timeout: Option<Duration>,
+    shutdown_timeout: Duration,
+    writer: SharedPool,
+    writer_queue: WriterQueue,
+    readers: SharedPool,
     reader_semaphore: Arc<Semaphore>,
+    reader_stats: Arc<StatsRecorder>,
+    writer_stats: Arc<StatsRecorder>,
+    group_commit: Option<GroupCommitQueue>,
+    changes: Arc<ChangeNotifier>,
+    health: HealthCheckConfig,
+    retry: RetryConfig,
+    // In-memory databases are freed once their last connection closes, so this
+    // keeps one open for as long as the pool is around.
+    memory_anchor: Option<Arc<std::sync::Mutex<Connection>>>,
     _marker: PhantomData<fn() -> DB>,
 }
 
 impl<DB> Clone for ConnectionPool<DB> {
     fn clone(&self) -> Self {
         Self {
+            name: self.name,
             connect_timeout: self.connect_timeout,
-            writer: self.writer.clone(),
-            writer_semaphore: Arc::clone(&self.writer_semaphore),
-            readers: self.readers.clone(),
+            statement_timeout: self.statement_timeout,
+            shutdown_timeout: self.shutdown_timeout,
+            writer: Arc::clone(&self.writer),
+            writer_queue: self.writer_queue.clone(),
+            readers: Arc::clone(&self.readers),
             reader_semaphore: Arc::clone(&self.reader_semaphore),
+            reader_stats: Arc::clone(&self.reader_stats),
+            writer_stats: Arc::clone(&self.writer_stats),
+            group_commit: self.group_commit.clone(),
+            changes: Arc::clone(&self.changes),
+            health: self.health.clone(),
+            retry: self.retry.clone(),
+            memory_anchor: self.memory_anchor.clone(),
             _marker: PhantomData,
         }
     }
@@ -110,19 +272,69 @@ impl<DB> Clone for ConnectionPool<DB> {
 
 impl<DB: 'static> ConnectionPool<DB> {
     /// Create a new pool with the given configuration.
-    fn new(config: &Config, initializers: Vec<PoolInitializer>) -> Result<Self> {
+    fn new(
+        name: &'static str,
+        config: &Config,
+        initializers: Vec<PoolInitializer>,
+    ) -> Result<Self> {
+        let (url, memory_anchor) = if config.url == MEMORY_URL {
+            let url = memory_url(name);
+            let anchor = Connection::open_with_flags(&url, writer_flags())?;
+            (url, Some(Arc::new(std::sync::Mutex::new(anchor))))
+        } else {
+            (config.url.clone(), None)
+        };
+        let changes = ChangeNotifier::new();
         // MUST create the writer before the reader or we get SQLITE_MISUSE (correctly!)
-        let writer = Some(create_pool(config, true, initializers.clone())?);
-        let readers = Some(create_pool(config, false, initializers)?);
-        let writer_semaphore = Arc::new(Semaphore::new(1));
+        let writer = Arc::new(RwLock::new(Some(create_pool(
+            name,
+            config,
+            &url,
+            true,
+            Some(&changes),
+            initializers.clone(),
+        )?)));
+        let readers = Arc::new(RwLock::new(Some(create_pool(
+            name,
+            config,
+            &url,
+            false,
+            None,
+            initializers,
+        )?)));
+        let writer_queue = WriterQueue::spawn(&config.write_priorities);
         let reader_semaphore = Arc::new(Semaphore::new(config.max_read_connections as usize));
         let connect_timeout = Duration::from_secs(config.connect_timeout);
+        let reader_stats = Arc::new(StatsRecorder::default());
+        let writer_stats = Arc::new(StatsRecorder::default());
+        let group_commit = config.group_commit.as_ref().map(|group_commit| {
+            GroupCommitQueue::spawn::<DB>(
+                name,
+                group_commit,
+                connect_timeout,
+                config.statement_timeout.map(Duration::from_millis),
+                writer_queue.clone(),
+                Arc::clone(&writer),
+                Arc::clone(&writer_stats),
+                Arc::clone(&changes),
+            )
+        });
         Ok(Self {
+            name,
             connect_timeout,
+            statement_timeout: config.statement_timeout.map(Duration::from_millis),
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout),
             writer,
-            writer_semaphore,
+            writer_queue,
             readers,
             reader_semaphore,
+            reader_stats,
+            writer_stats,
+            group_commit,
+            changes,
+            health: config.health.clone(),
+            retry: config.retry.clone(),
+            memory_anchor,
             _marker: PhantomData,
         })
     }
@@ -131,7 +343,7 @@ impl<DB: 'static> ConnectionPool<DB> {
     fn get_config(rocket: &Rocket<Build>, db: &'static str) -> Result<Config> {
         Config::from(db, rocket).map_err(|e| {
             rocket::error!("Error configuring database {}: {}", db, e.to_string());
-            Error::Configuration(Box::new(e))
+            Error::Configuration(e)
         })
     }
 
@@ -143,19 +355,16 @@ impl<DB: 'static> ConnectionPool<DB> {
         initializers: Vec<PoolInitializer>,
     ) -> Result<Self> {
         let config = Self::get_config(rocket, db)?;
-        let pool = Self::new(&config, initializers)?;
+        let pool = Self::new(db, &config, initializers)?;
         let migration_config = config.migrate;
-        let pool_inner = pool
-            .writer
-            .as_ref()
-            .cloned()
-            .expect("internal invariant broken: self.pool is Some");
+        let pool_inner =
+            current_pool(&pool.writer).expect("internal invariant broken: self.pool is Some");
         let mut connection = pool_inner
             .get_timeout(pool.connect_timeout)
             .map_err(Error::ConnectionFailure)?;
         // TODO: Trace
         run_migrations::<T>(db, &migration_config, &mut connection).map_err(|e| {
-            println!("Error running migrations: {e:?}");
+            rocket::error!("Error running migrations on database {}: {:?}", db, e);
             e
         })?;
         Ok(pool)
@@ -168,14 +377,21 @@ impl<DB: 'static> ConnectionPool<DB> {
         initializers: Vec<PoolInitializer>,
     ) -> impl Fairing {
         AdHoc::try_on_ignite(fairing_name, move |rocket| async move {
-            match Self::get_config(&rocket, db) {
-                Ok(config) => Ok(rocket.manage(Self::new(&config, initializers))),
+            match Self::get_config(&rocket, db)
+                .and_then(|config| Self::new(db, &config, initializers))
+            {
+                Ok(pool) => Ok(rocket
+                    .manage(pool)
+                    .attach(Self::shutdown_fairing(fairing_name))
+                    .attach(Self::read_cache_fairing(fairing_name))),
                 Err(_) => Err(rocket),
             }
         })
     }
 
     /// Fairing to attach to your rocket instance, which will run migrations on startup.
+    // Rocket's fairings hand the instance back on failure, which makes for a large error.
+    #[allow(clippy::result_large_err)]
     pub fn fairing_with_migrations<T: RustEmbed>(
         fairing_name: &'static str,
         db: &'static str,
@@ -184,7 +400,10 @@ impl<DB: 'static> ConnectionPool<DB> {
         AdHoc::try_on_ignite(fairing_name, move |rocket| async move {
             run_blocking(move || {
                 match Self::get_pool_with_migrations_impl::<T>(&rocket, db, initializers) {
-                    Ok(pool) => Ok(rocket.manage(pool)),
+                    Ok(pool) => Ok(rocket
+                        .manage(pool)
+                        .attach(Self::shutdown_fairing(fairing_name))
+                        .attach(Self::read_cache_fairing(fairing_name))),
                     Err(_) => Err(rocket),
                 }
             })
@@ -192,31 +411,126 @@ impl<DB: 'static> ConnectionPool<DB> {
         })
     }
 
+    /// Fairing which shuts down the pool when rocket shuts down.
+    fn shutdown_fairing(fairing_name: &'static str) -> impl Fairing {
+        AdHoc::on_shutdown(fairing_name, |rocket| {
+            Box::pin(async move {
+                if let Some(pool) = Self::get_pool(rocket) {
+                    pool.shutdown().await;
+                }
+            })
+        })
+    }
+
+    /// Fairing which releases the request's cached read connection once the handler
+    /// has returned, before the response is sent.
+    fn read_cache_fairing(fairing_name: &'static str) -> impl Fairing {
+        AdHoc::on_response(fairing_name, |request, _| {
+            Box::pin(async move {
+                request
+                    .local_cache(ReadCache::<DB>::default)
+                    .release()
+                    .await;
+            })
+        })
+    }
+
+    /// Stop handing out connections and taking grouped writes, wait (up to the shutdown
+    /// timeout) for the current writer to finish, checkpoint the WAL, and close all
+    /// connections. Connections still in use are closed once they are released.
+    pub async fn shutdown(&self) {
+        if let Some(group_commit) = &self.group_commit {
+            group_commit.close();
+        }
+        self.reader_semaphore.close();
+        match timeout(self.shutdown_timeout, self.writer_queue.close()).await {
+            Ok(Some(permit)) => {
+                if let Some(writer) = current_pool(&self.writer) {
+                    let connect_timeout = self.connect_timeout;
+                    let result = run_blocking(move || match writer.get_timeout(connect_timeout) {
+                        Ok(connection) => checkpoint(&connection).map_err(Error::Rusqlite),
+                        Err(e) => Err(Error::ConnectionFailure(e)),
+                    })
+                    .await;
+                    if let Err(e) = result {
+                        rocket::error!("failed to checkpoint database {}: {}", self.name, e);
+                    }
+                }
+                drop(permit);
+            }
+            // Someone else already shut the pool down.
+            Ok(None) => {}
+            Err(_) => {
+                rocket::error!(
+                    "timed out waiting for the writer to finish on database {}, skipping checkpoint",
+                    self.name
+                );
+            }
+        }
+
+        let writer = self
+            .writer
+            .write()
+            .expect("internal invariant broken: pool lock is never poisoned")
+            .take();
+        let readers = self
+            .readers
+            .write()
+            .expect("internal invariant broken: pool lock is never poisoned")
+            .take();
+        run_blocking(move || {
+            drop(writer);
+            drop(readers);
+        })
+        .await;
+    }
+
     /// Helper method for getting a connection of a given type.
-    async fn get_conn_inner<C>(
+    pub(crate) async fn get_conn_inner<C>(
         connect_timeout: Duration,
-        semaphore: Arc<Semaphore>,
-        pool: &Option<Pool<SqliteConnectionManager>>,
+        statement_timeout: Option<Duration>,
+        permit: impl Future<Output = Result<OwnedSemaphorePermit>> + Send,
+        pool: &SharedPool,
+        stats: &Arc<StatsRecorder>,
+        changes: Option<&Arc<ChangeNotifier>>,
+        span: Span,
     ) -> Result<C>
     where
         C: From<ConnectionHolder>,
     {
-        let permit = if let Ok(permit) = timeout(connect_timeout, semaphore.acquire_owned()).await {
-            permit.expect("internal invariant broken: semaphore should not be closed")
-        } else {
+        let waiting = stats.wait();
+        let started = Instant::now();
+        let permit = timeout(connect_timeout, permit)
+            .instrument(trace::acquire_permit(&span))
+            .await;
+        stats.record_permit_wait(started.elapsed(), permit.is_err());
+        drop(waiting);
+        let Ok(permit) = permit else {
             rocket::error!("database connection retrieval timed out");
             return Err(Error::ConnectionPermitRetrievalTimeout);
         };
+        let permit = permit?;
 
-        let pool = pool
-            .as_ref()
-            .cloned()
-            .expect("internal invariant broken: self.pool is Some");
+        let Some(pool) = current_pool(pool) else {
+            return Err(Error::ShuttingDown);
+        };
 
-        match run_blocking(move || pool.get_timeout(connect_timeout)).await {
+        let started = Instant::now();
+        let connection = run_blocking(move || pool.get_timeout(connect_timeout))
+            .instrument(trace::checkout(&span))
+            .await;
+        stats.record_checkout(started.elapsed(), connection.is_ok());
+        match connection {
             Ok(c) => Ok(ConnectionHolder {
+                interrupt: c.get_interrupt_handle(),
+                statement_timeout,
                 connection: Arc::new(Mutex::new(Some(c))),
                 permit: Some(permit),
+                stats: Arc::clone(stats),
+                changes: changes.cloned(),
+                callbacks: std::sync::Mutex::default(),
+                acquired_at: Instant::now(),
+                span,
             }
             .into()),
             Err(e) => {
@@ -228,10 +542,22 @@ impl<DB: 'static> ConnectionPool<DB> {
 
     /// Get a read connection.
     pub(crate) async fn get_read(&self) -> Result<ReadConnection<DB>> {
+        let semaphore = Arc::clone(&self.reader_semaphore);
+        // The semaphore is only closed when shutting down.
+        let permit = async move {
+            semaphore
+                .acquire_owned()
+                .await
+                .map_err(|_| Error::ShuttingDown)
+        };
         Self::get_conn_inner(
             self.connect_timeout,
-            Arc::clone(&self.reader_semaphore),
+            self.statement_timeout,
+            permit,
             &self.readers,
+            &self.reader_stats,
+            None,
+            trace::connection(self.name, "reader", None),
         )
         .await
     }
@@ -239,20 +565,96 @@ impl<DB: 'static> ConnectionPool<DB> {
     /// Get a write connection.
     pub(crate) async fn get_write(
         &self,
-        _authorization: WriteAuthorization,
+        authorization: WriteAuthorization,
     ) -> Result<WriteConnection<DB>> {
-        Self::get_conn_inner(
+        let span = trace::connection(self.name, "writer", Some(&authorization));
+        let connection: WriteConnection<DB> = Self::get_conn_inner(
             self.connect_timeout,
-            Arc::clone(&self.writer_semaphore),
+            self.statement_timeout,
+            self.writer_queue.acquire(&authorization),
             &self.writer,
+            &self.writer_stats,
+            Some(&self.changes),
+            span,
         )
-        .await
+        .await?;
+        Ok(connection.with_retry(self.retry.clone()))
+    }
+
+    /// Subscribe to the rows changed by writes committed from now on. Each change
+    /// is delivered once the transaction making it has committed, so it is visible
+    /// to readers by the time it is received. Changes made inside this crate's own
+    /// savepoints (i.e. [`Nested`](crate::Nested) and group commit) which were rolled
+    /// back are not delivered, but those made inside savepoints opened any other way
+    /// (e.g. with [`Transaction::savepoint`] or `SAVEPOINT`) are delivered as long as
+    /// the transaction commits. Subscribers which fall too far behind miss changes,
+    /// and are told so with [`broadcast::error::RecvError::Lagged`].
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<Change> {
+        self.changes.subscribe()
+    }
+
+    /// The name of the database this pool is for.
+    #[inline]
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Routes which render the metrics for this pool in the Prometheus text
+    /// exposition format. Mount these under a different base for each database.
+    pub fn metrics_routes() -> Vec<Route> {
+        vec![Route::new(
+            Method::Get,
+            "/metrics",
+            MetricsHandler::<DB>::new(),
+        )]
+    }
+
+    /// Routes which report the health of this pool as JSON, responding with
+    /// 503 if any check fails. Mount these under a different base for each database.
+    pub fn health_routes() -> Vec<Route> {
+        vec![Route::new(
+            Method::Get,
+            "/health",
+            HealthHandler::<DB>::new(),
+        )]
+    }
+
+    /// Fairing which mounts [`Self::health_routes`] under the given base.
+    pub fn health_fairing(base: &'static str) -> impl Fairing {
+        AdHoc::on_ignite("Database Health Check", move |rocket| async move {
+            rocket.mount(base, Self::health_routes())
+        })
+    }
+
+    /// Check the health of this pool.
+    pub async fn check_health(&self) -> HealthReport {
+        check_health(self).await
+    }
+
+    /// The configuration for health checks.
+    pub(crate) const fn health_config(&self) -> &HealthCheckConfig {
+        &self.health
+    }
+
+    /// Get a snapshot of the statistics for this pool.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            readers: self
+                .reader_stats
+                .snapshot(current_pool(&self.readers).as_ref()),
+            writer: self
+                .writer_stats
+                .snapshot(current_pool(&self.writer).as_ref()),
+        }
     }
 
     /// Get a connector for this pool.
     #[inline]
     pub const fn get(&self) -> Connector<'_, DB> {
-        Connector { pool: self }
+        Connector {
+            pool: self,
+            read_cache: None,
+        }
     }
 
     /// Get a connector from the rocket instance
@@ -274,34 +676,117 @@ impl<DB: 'static> ConnectionPool<DB> {
     /// against the connection
     pub async fn connect_and_read<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(&Connection) -> R + Send,
-        R: Send,
+        F: FnOnce(&Connection) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        Ok(self.get_read().await?.run(f).await)
+        self.get_read().await?.run(f).await
     }
 
     /// Get a read-only connection from the pool and run the provided function against
     /// the connection inside a transaction
     pub async fn connect_and_read_with_transaction<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(Transaction) -> R + Send,
-        R: Send,
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        Ok(self.get_read().await?.run_with_transaction(f).await)
+        self.get_read().await?.run_with_transaction(f).await
     }
 
     /// Get a write connection from the pool and run the provided function against
-    /// the connection inside a transaction
+    /// the connection inside a transaction. As the function is handed the transaction
+    /// itself, and so decides whether to commit it, calls are never coalesced by group
+    /// commit; use [`ConnectionPool::connect_and_write_grouped`] for that.
     pub async fn connect_and_write<F, R>(
         &self,
         authorization: WriteAuthorization,
         f: F,
     ) -> Result<R>
     where
-        F: FnOnce(Transaction) -> R + Send,
-        R: Send,
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        Ok(self.get_write(authorization).await?.run(f).await)
+        self.get_write(authorization).await?.run(f).await
+    }
+
+    /// Get a write connection from the pool and run the provided function against
+    /// the connection inside a transaction, committing it if the function returns `Ok`
+    /// and rolling it back if it returns `Err`.
+    pub async fn try_connect_and_write<F, R, E>(
+        &self,
+        authorization: WriteAuthorization,
+        f: F,
+    ) -> Result<R, TransactionError<E>>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        self.get_write(authorization).await?.try_run(f).await
+    }
+
+    /// Get a write connection from the pool and run the provided function against
+    /// the connection inside a transaction, committing it if the function returns `Ok`.
+    /// The transaction is retried if the database is busy or locked, with the write
+    /// connection released while backing off so that other writes can go ahead.
+    pub async fn connect_and_write_with_retry<F, R>(
+        &self,
+        authorization: WriteAuthorization,
+        f: F,
+    ) -> Result<R>
+    where
+        F: Fn(&Transaction) -> Result<R, rusqlite::Error> + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        let f = Arc::new(f);
+        let mut retry = Retry::new(&self.retry);
+        loop {
+            let result = self
+                .get_write(authorization.clone())
+                .await?
+                .attempt(Arc::clone(&f))
+                .await;
+            match retry.next(result) {
+                ControlFlow::Continue(backoff) => tokio::time::sleep(backoff).await,
+                ControlFlow::Break(result) => return result,
+            }
+        }
+    }
+
+    /// Run the provided function against the writer inside a savepoint, committing
+    /// if it returns `Ok`. If group commit is configured, concurrent calls are
+    /// coalesced into a single transaction, and an `Err` (or a panic) only rolls
+    /// back the savepoint of the call that returned it. If committing the shared
+    /// transaction fails, every call in it gets [`Error::GroupCommit`].
+    ///
+    /// This is separate from [`ConnectionPool::connect_and_write`] because coalescing
+    /// needs the transaction to stay under the pool's control: the function only
+    /// borrows it, and says whether its own changes should be kept by returning
+    /// `Ok` or `Err`, rather than committing or rolling back the whole thing.
+    pub async fn connect_and_write_grouped<F, R, E>(
+        &self,
+        authorization: WriteAuthorization,
+        f: F,
+    ) -> Result<Result<R, E>>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        if let Some(group_commit) = &self.group_commit {
+            return group_commit.submit(authorization, f).await;
+        }
+        self.get_write(authorization)
+            .await?
+            .run(move |transaction| {
+                let result = f(&transaction);
+                if result.is_ok() {
+                    trace::commit().in_scope(|| transaction.commit())?;
+                } else {
+                    trace::rollback().in_scope(|| drop(transaction));
+                }
+                Ok(result)
+            })
+            .await?
     }
 
     /// Get the pool from the rocket instance
@@ -313,12 +798,15 @@ impl<DB: 'static> ConnectionPool<DB> {
 
 impl<DB> Drop for ConnectionPool<DB> {
     fn drop(&mut self) {
-        let writer = self.writer.take();
-        let readers = self.readers.take();
+        // Only the last clone to go away gets to close the connections.
+        let writer = take_if_unique(&mut self.writer);
+        let readers = take_if_unique(&mut self.readers);
+        let memory_anchor = self.memory_anchor.take().and_then(Arc::into_inner);
         if let Ok(handle) = tokio::runtime::Handle::try_current() {
             handle.spawn_blocking(move || {
                 drop(writer);
                 drop(readers);
+                drop(memory_anchor);
             });
         }
     }
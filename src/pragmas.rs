@@ -72,14 +72,14 @@ impl Default for Pragmas {
 impl Pragmas {
     pub(crate) fn set(&self, connection: &RusqliteConnection) -> Result<(), rusqlite::Error> {
         let mut query = format!(
-            r#"
+            r"
 PRAGMA page_size = {};
 PRAGMA locking_mode = {};
 PRAGMA journal_mode = {};
 PRAGMA foreign_keys = {};
 PRAGMA synchronous = {};
 PRAGMA auto_vacuum = {};
-"#,
+",
             self.page_size,
             self.locking_mode,
             self.journal_mode,
@@ -92,4 +92,27 @@ PRAGMA auto_vacuum = {};
         }
         connection.execute_batch(&query)
     }
+
+    /// Set the pragmas which apply to a single database on the given attached schema.
+    pub(crate) fn set_attached(
+        &self,
+        connection: &RusqliteConnection,
+        schema: &str,
+    ) -> Result<(), rusqlite::Error> {
+        let schema = quote_identifier(schema);
+        connection.execute_batch(&format!(
+            r"
+PRAGMA {schema}.page_size = {};
+PRAGMA {schema}.journal_mode = {};
+PRAGMA {schema}.synchronous = {};
+PRAGMA {schema}.auto_vacuum = {};
+",
+            self.page_size, self.journal_mode, self.synchronous, self.auto_vacuum
+        ))
+    }
+}
+
+/// Quote the given identifier for use in SQL.
+pub fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
 }
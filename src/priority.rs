@@ -0,0 +1,236 @@
+use crate::{config::WritePriorityConfig, Error, WriteAuthorization};
+
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+
+/// A writer waiting for the permit.
+struct Waiter {
+    priority: u8,
+    skips: usize,
+    sender: oneshot::Sender<OwnedSemaphorePermit>,
+}
+
+enum Message {
+    /// Wait for the permit.
+    Wait(Waiter),
+    /// Stop handing out the permit, and hand it to the sender once the current
+    /// writer is done with it.
+    Close(oneshot::Sender<OwnedSemaphorePermit>),
+}
+
+/// Pick the next waiter to hand the permit to. Waiters which have been overtaken
+/// `max_skips` times go first (in arrival order), otherwise the earliest waiter
+/// with the highest priority does.
+fn next_waiter(waiters: &mut Vec<Waiter>, max_skips: usize) -> Option<Waiter> {
+    // Anyone who has stopped waiting (e.g. timed out) is no longer interested.
+    waiters.retain(|waiter| !waiter.sender.is_closed());
+    let index = waiters
+        .iter()
+        .position(|waiter| waiter.skips >= max_skips)
+        .or_else(|| {
+            waiters
+                .iter()
+                .enumerate()
+                .rev()
+                .max_by_key(|(_, waiter)| waiter.priority)
+                .map(|(index, _)| index)
+        })?;
+    for waiter in &mut waiters[..index] {
+        waiter.skips += 1;
+    }
+    Some(waiters.remove(index))
+}
+
+/// Hand out the single writer permit to waiters in priority order, until closed.
+async fn dispatch(mut receiver: mpsc::UnboundedReceiver<Message>, max_skips: usize) {
+    let semaphore = Arc::new(Semaphore::new(1));
+    let mut waiters = Vec::new();
+    let closer = 'dispatch: loop {
+        if waiters.is_empty() {
+            match receiver.recv().await {
+                Some(Message::Wait(waiter)) => waiters.push(waiter),
+                Some(Message::Close(closer)) => break closer,
+                None => return,
+            }
+        }
+
+        // Keep accepting new waiters while the current writer holds the permit,
+        // so that they can be considered when it is released.
+        let mut permit = tokio::select! {
+            permit = Arc::clone(&semaphore).acquire_owned() => {
+                permit.expect("internal invariant broken: semaphore should not be closed")
+            }
+            message = receiver.recv() => match message {
+                Some(Message::Wait(waiter)) => {
+                    waiters.push(waiter);
+                    continue;
+                }
+                Some(Message::Close(closer)) => break closer,
+                None => return,
+            },
+        };
+        while let Ok(message) = receiver.try_recv() {
+            match message {
+                Message::Wait(waiter) => waiters.push(waiter),
+                Message::Close(closer) => {
+                    drop(permit);
+                    break 'dispatch closer;
+                }
+            }
+        }
+
+        // If nobody is left to take the permit, it is simply released.
+        while let Some(waiter) = next_waiter(&mut waiters, max_skips) {
+            match waiter.sender.send(permit) {
+                Ok(()) => break,
+                Err(returned) => permit = returned,
+            }
+        }
+    };
+
+    // Anyone still waiting (or who tries to wait from now on) is turned away.
+    receiver.close();
+    drop(waiters);
+    let permit = semaphore
+        .acquire_owned()
+        .await
+        .expect("internal invariant broken: semaphore should not be closed");
+    let _ = closer.send(permit);
+}
+
+/// Queue for the writer permit, which serves writers based on the priority of
+/// their [`WriteAuthorization`].
+#[derive(Clone)]
+pub struct WriterQueue {
+    config: WritePriorityConfig,
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl WriterQueue {
+    /// Spawn the task which hands out the writer permit.
+    pub fn spawn(config: &WritePriorityConfig) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(dispatch(receiver, config.max_skips));
+        Self {
+            config: config.clone(),
+            sender,
+        }
+    }
+
+    /// The priority of writes with the given authorization.
+    pub const fn priority(&self, authorization: &WriteAuthorization) -> u8 {
+        match authorization {
+            WriteAuthorization::PassedCsrfChecks => self.config.request,
+            WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest => {
+                self.config.background_job
+            }
+            WriteAuthorization::ThisIsALoggingEndpointAndSafeToWriteWithoutProtection => {
+                self.config.logging
+            }
+        }
+    }
+
+    /// Wait for the writer permit.
+    pub async fn acquire(
+        &self,
+        authorization: &WriteAuthorization,
+    ) -> Result<OwnedSemaphorePermit, Error> {
+        let (sender, receiver) = oneshot::channel();
+        let waiter = Waiter {
+            priority: self.priority(authorization),
+            skips: 0,
+            sender,
+        };
+        self.sender
+            .send(Message::Wait(waiter))
+            .map_err(|_| Error::ShuttingDown)?;
+        receiver.await.map_err(|_| Error::ShuttingDown)
+    }
+
+    /// Stop handing out the writer permit, and wait for the current writer to
+    /// finish. Returns the permit, or `None` if the queue was already closed.
+    pub async fn close(&self) -> Option<OwnedSemaphorePermit> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender.send(Message::Close(sender)).ok()?;
+        receiver.await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Waiters with the given priorities, in arrival order, along with the receivers
+    /// which keep them waiting.
+    fn waiters(priorities: &[u8]) -> (Vec<Waiter>, Vec<oneshot::Receiver<OwnedSemaphorePermit>>) {
+        priorities
+            .iter()
+            .map(|&priority| {
+                let (sender, receiver) = oneshot::channel();
+                let waiter = Waiter {
+                    priority,
+                    skips: 0,
+                    sender,
+                };
+                (waiter, receiver)
+            })
+            .unzip()
+    }
+
+    fn next_priority(waiters: &mut Vec<Waiter>, max_skips: usize) -> Option<u8> {
+        next_waiter(waiters, max_skips).map(|waiter| waiter.priority)
+    }
+
+    #[test]
+    fn highest_priority_goes_first() {
+        let (mut waiters, _receivers) = waiters(&[1, 3, 2]);
+        assert_eq!(next_priority(&mut waiters, 10), Some(3));
+        assert_eq!(next_priority(&mut waiters, 10), Some(2));
+        assert_eq!(next_priority(&mut waiters, 10), Some(1));
+        assert_eq!(next_priority(&mut waiters, 10), None);
+    }
+
+    #[test]
+    fn earliest_goes_first_among_equal_priorities() {
+        let (mut waiters, _receivers) = waiters(&[1, 2, 2]);
+        next_waiter(&mut waiters, 10);
+        // The first waiter with priority 2 was picked, so only the one before it was skipped.
+        assert_eq!(
+            waiters
+                .iter()
+                .map(|waiter| (waiter.priority, waiter.skips))
+                .collect::<Vec<_>>(),
+            [(1, 1), (2, 0)]
+        );
+    }
+
+    #[test]
+    fn overtaken_waiters_are_not_starved() {
+        let (mut waiters, _receivers) = waiters(&[1, 5, 5, 5]);
+        assert_eq!(next_priority(&mut waiters, 2), Some(5));
+        assert_eq!(next_priority(&mut waiters, 2), Some(5));
+        // It has been overtaken twice, so goes ahead of the remaining higher priority waiter.
+        assert_eq!(next_priority(&mut waiters, 2), Some(1));
+        assert_eq!(next_priority(&mut waiters, 2), Some(5));
+    }
+
+    #[test]
+    fn starved_waiters_go_in_arrival_order() {
+        let (mut waiters, _receivers) = waiters(&[1, 2, 5]);
+        for waiter in &mut waiters[..2] {
+            waiter.skips = 3;
+        }
+        assert_eq!(next_priority(&mut waiters, 3), Some(1));
+        assert_eq!(next_priority(&mut waiters, 3), Some(2));
+        assert_eq!(next_priority(&mut waiters, 3), Some(5));
+    }
+
+    #[test]
+    fn waiters_which_stopped_waiting_are_dropped() {
+        let (mut waiters, mut receivers) = waiters(&[1, 3, 2]);
+        drop(receivers.remove(1));
+        assert_eq!(next_priority(&mut waiters, 10), Some(2));
+        assert_eq!(waiters.len(), 1);
+    }
+}
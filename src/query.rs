@@ -1,3 +1,5 @@
+use crate::trace;
+
 use rusqlite::{Connection, OptionalExtension, Transaction};
 use serde::{de::DeserializeOwned, Serialize};
 use serde_rusqlite::{columns_from_statement, from_row_with_columns, to_params, to_params_named};
@@ -8,6 +10,7 @@ pub fn execute_with_params<T: Serialize>(
     transaction: &Transaction,
     params: &T,
 ) -> Result<usize, rusqlite::Error> {
+    let _span = trace::statement(query).entered();
     let mut statement = transaction.prepare_cached(query)?;
     let modified = statement.execute(
         to_params(params).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?,
@@ -21,6 +24,7 @@ pub fn execute_with_params_named<T: Serialize>(
     transaction: &Transaction,
     params: &T,
 ) -> Result<usize, rusqlite::Error> {
+    let _span = trace::statement(query).entered();
     let mut statement = transaction.prepare_cached(query)?;
     let modified = statement.execute(
         to_params_named(params)
@@ -37,6 +41,7 @@ pub fn query_with_params<Input: Serialize, Output: DeserializeOwned>(
     connection: &Connection,
     params: &Input,
 ) -> Result<Vec<Output>, rusqlite::Error> {
+    let _span = trace::statement(query).entered();
     let mut statement = connection.prepare_cached(query)?;
     let columns = columns_from_statement(&statement);
     let result = statement
@@ -63,6 +68,7 @@ pub fn query_with_params_named<Input: Serialize, Output: DeserializeOwned>(
     connection: &Connection,
     params: &Input,
 ) -> Result<Vec<Output>, rusqlite::Error> {
+    let _span = trace::statement(query).entered();
     let mut statement = connection.prepare_cached(query)?;
     let columns = columns_from_statement(&statement);
     let result = statement
@@ -91,6 +97,7 @@ pub fn query_without_params<Output: DeserializeOwned>(
     query: &str,
     connection: &Connection,
 ) -> Result<Vec<Output>, rusqlite::Error> {
+    let _span = trace::statement(query).entered();
     let mut statement = connection.prepare_cached(query)?;
     let columns = columns_from_statement(&statement);
     let result = statement
@@ -117,6 +124,7 @@ pub fn query_optional_with_params<Input: Serialize, Output: DeserializeOwned>(
     connection: &Connection,
     params: &Input,
 ) -> Result<Option<Output>, rusqlite::Error> {
+    let _span = trace::statement(query).entered();
     let mut statement = connection.prepare_cached(query)?;
     let columns = columns_from_statement(&statement);
     let result = statement
@@ -143,6 +151,7 @@ pub fn query_optional_with_params_named<Input: Serialize, Output: DeserializeOwn
     connection: &Connection,
     params: &Input,
 ) -> Result<Option<Output>, rusqlite::Error> {
+    let _span = trace::statement(query).entered();
     let mut statement = connection.prepare_cached(query)?;
     let columns = columns_from_statement(&statement);
     let result = statement
@@ -1,10 +1,12 @@
-use crate::holder::ConnectionHolder;
+use crate::{holder::ConnectionHolder, manager::ConnectionManager, trace, ConnectionPool, Error};
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc, time::Duration};
 
 use r2d2::PooledConnection;
-use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{Connection, Transaction, TransactionBehavior};
+use tokio::sync::Mutex;
+
+type Result<T, E = Error> = anyhow::Result<T, E>;
 
 /// A read-only connection to the database.
 pub struct ReadConnection<DB> {
@@ -24,33 +26,113 @@ impl<DB> From<ConnectionHolder> for ReadConnection<DB> {
 impl<DB: 'static> ReadConnection<DB> {
     /// Run the provided function against the connection
     #[inline]
-    pub async fn run<F, R>(&self, f: F) -> R
+    pub async fn run<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(&Connection) -> R + Send,
-        R: Send,
+        F: FnOnce(&Connection) -> R + Send + 'static,
+        R: Send + 'static,
     {
         let with_connection =
-            move |connection: &mut PooledConnection<SqliteConnectionManager>| f(connection);
+            move |connection: &mut PooledConnection<ConnectionManager>| f(connection);
         self.holder.run(with_connection).await
     }
 
+    /// Run the provided function against the connection, aborting its queries (and
+    /// returning [`Error::StatementTimeout`]) if they run for longer than the given
+    /// time, instead of the configured statement timeout.
+    pub async fn run_with_statement_timeout<F, R>(
+        &self,
+        statement_timeout: Duration,
+        f: F,
+    ) -> Result<R>
+    where
+        F: FnOnce(&Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let with_connection =
+            move |connection: &mut PooledConnection<ConnectionManager>| f(connection);
+        self.holder
+            .run_with_statement_timeout(Some(statement_timeout), with_connection)
+            .await
+    }
+
+    /// Run the provided function against the connection, interrupting it (and
+    /// returning [`Error::Interrupted`]) if it doesn't finish within the given time.
+    pub async fn run_with_timeout<F, R>(&self, timeout: Duration, f: F) -> Result<R>
+    where
+        F: FnOnce(&Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        tokio::time::timeout(timeout, self.run(f))
+            .await
+            .map_err(|_| Error::Interrupted)?
+    }
+
     /// Run the provided function against the connection inside a transaction
     #[inline]
-    pub async fn run_with_transaction<F, R>(&self, f: F) -> R
+    pub async fn run_with_transaction<F, R>(&self, f: F) -> Result<R>
     where
-        F: FnOnce(Transaction) -> R + Send,
-        R: Send,
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        let with_transaction = move |connection: &mut PooledConnection<SqliteConnectionManager>| {
-            // TODO: Better error handling
-            let transaction = connection
-                .transaction_with_behavior(TransactionBehavior::Deferred)
-                .expect("internal invariant broken: couldn't create a transaction");
-            f(transaction)
+        let stats = Arc::clone(&self.holder.stats);
+        let with_transaction = move |connection: &mut PooledConnection<ConnectionManager>| {
+            let transaction = trace::begin()
+                .in_scope(|| connection.transaction_with_behavior(TransactionBehavior::Deferred))
+                .map_err(Error::TransactionBegin)?;
+            // The function commits or rolls back the transaction by the time it returns.
+            let _timer = stats.time_transaction();
+            Ok(f(transaction))
         };
-        self.holder.run(with_transaction).await
+        self.holder.run(with_transaction).await?
     }
 }
 
 crate::define_from_request_for_gettable_connection!(ReadConnection, get_read);
 crate::define_sentinel_for_gettable_connection!(ReadConnection);
+
+/// A read-only connection shared by all reads within a single request. It is checked
+/// out on first use, and released once the handler has returned, so that it isn't held
+/// while the response is sent (which, for a stream, can take as long as the client likes).
+pub struct ReadCache<DB> {
+    state: Mutex<CacheState<DB>>,
+}
+
+struct CacheState<DB> {
+    connection: Option<Arc<ReadConnection<DB>>>,
+    released: bool,
+}
+
+impl<DB> Default for ReadCache<DB> {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(CacheState {
+                connection: None,
+                released: false,
+            }),
+        }
+    }
+}
+
+impl<DB: 'static> ReadCache<DB> {
+    /// Get the cached connection, checking one out from the pool if needed. Once the
+    /// cache has been released, a new connection is checked out each time instead.
+    pub async fn get(&self, pool: &ConnectionPool<DB>) -> Result<Arc<ReadConnection<DB>>> {
+        let mut state = self.state.lock().await;
+        if state.released {
+            return Ok(Arc::new(pool.get_read().await?));
+        }
+        if let Some(connection) = &state.connection {
+            return Ok(Arc::clone(connection));
+        }
+        let connection = Arc::new(pool.get_read().await?);
+        state.connection = Some(Arc::clone(&connection));
+        Ok(connection)
+    }
+
+    /// Give the cached connection back to the pool, once any reads still using it finish.
+    pub async fn release(&self) {
+        let mut state = self.state.lock().await;
+        state.released = true;
+        state.connection = None;
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{
+    callbacks,
+    changes::{self, SavepointMark},
+};
+
+use rusqlite::{Connection, Transaction};
+
+// Savepoints with the same name nest, with RELEASE and ROLLBACK TO acting on
+// the innermost one.
+const SAVEPOINT: &str = "SAVEPOINT nested";
+const RELEASE: &str = "RELEASE nested";
+const ROLLBACK: &str = "ROLLBACK TO nested; RELEASE nested";
+
+/// Rolls the savepoint back if it is dropped before being finished, e.g. when
+/// the function run inside it panics.
+struct Guard<'a> {
+    connection: &'a Connection,
+    changes: Option<SavepointMark>,
+    finished: bool,
+}
+
+impl Guard<'_> {
+    fn release(mut self) -> Result<(), rusqlite::Error> {
+        self.connection.execute_batch(RELEASE)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    fn rollback(mut self) -> Result<(), rusqlite::Error> {
+        self.finished = true;
+        self.rollback_inner()
+    }
+
+    fn rollback_inner(&mut self) -> Result<(), rusqlite::Error> {
+        // Before rolling back, as releasing the outermost savepoint commits.
+        if let Some(changes) = self.changes.take() {
+            changes.rollback();
+        }
+        self.connection.execute_batch(ROLLBACK)
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.rollback_inner();
+        }
+    }
+}
+
+fn run_nested<T, F, R, E>(connection: &Connection, target: &T, f: F) -> Result<R, E>
+where
+    F: FnOnce(&T) -> Result<R, E>,
+    E: From<rusqlite::Error>,
+{
+    connection.execute_batch(SAVEPOINT)?;
+    let guard = Guard {
+        connection,
+        changes: changes::savepoint(),
+        finished: false,
+    };
+    let (result, nested) = callbacks::savepoint(|| f(target));
+    match result {
+        Ok(value) => {
+            // If releasing fails, the savepoint is rolled back instead.
+            let released = guard.release();
+            callbacks::finish_savepoint(nested, released.is_ok());
+            released?;
+            Ok(value)
+        }
+        Err(e) => {
+            callbacks::finish_savepoint(nested, false);
+            guard.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+/// Run all-or-nothing steps inside a savepoint, at any level of nesting.
+pub trait Nested {
+    /// Run the provided function inside a savepoint, which is released if it returns
+    /// `Ok`, and rolled back if it returns `Err` or panics. The enclosing transaction
+    /// (or savepoint) is left open either way.
+    fn nested<F, R, E>(&self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&Self) -> Result<R, E>,
+        E: From<rusqlite::Error>;
+}
+
+impl Nested for Connection {
+    fn nested<F, R, E>(&self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&Self) -> Result<R, E>,
+        E: From<rusqlite::Error>,
+    {
+        run_nested(self, self, f)
+    }
+}
+
+impl Nested for Transaction<'_> {
+    fn nested<F, R, E>(&self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&Self) -> Result<R, E>,
+        E: From<rusqlite::Error>,
+    {
+        run_nested(self, self, f)
+    }
+}
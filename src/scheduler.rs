@@ -0,0 +1,455 @@
+//! Tasks run on a schedule, by at most one of the instances sharing a database at a time.
+
+use crate::{
+    background::{self, Lease, AUTHORIZATION},
+    config::SchedulerConfig,
+    util::{unix_millis, unix_millis_after},
+    AuthorizedConnector, ConnectionPool, Database, Error, TransactionError,
+};
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{TimeZone, Utc};
+use rocket::fairing::Fairing;
+use rusqlite::{named_params, Connection, Transaction};
+use tokio::sync::watch;
+
+const COMPONENT: &str = "scheduler";
+
+const MIGRATIONS: &[&str] = &["CREATE TABLE rocket_sqlite_rw_pool_scheduled_tasks (
+    name TEXT PRIMARY KEY NOT NULL,
+    -- Milliseconds since the Unix epoch, as are the other times.
+    last_run_at INTEGER,
+    last_error TEXT,
+    -- Set while the task runs, so that no other instance runs it at the same time.
+    lease_until INTEGER,
+    created_at INTEGER NOT NULL
+)"];
+
+const REGISTER: &str = "INSERT INTO rocket_sqlite_rw_pool_scheduled_tasks (name, created_at)
+VALUES (:name, :now)
+ON CONFLICT (name) DO NOTHING";
+
+// Tasks whose lease ran out may run again, as whoever was running them went away.
+const IDLE: &str = "SELECT name, last_run_at, created_at FROM rocket_sqlite_rw_pool_scheduled_tasks
+WHERE name IN (SELECT value FROM json_each(:names))
+    AND (lease_until IS NULL OR lease_until <= :now)";
+
+const CLAIM: &str = "UPDATE rocket_sqlite_rw_pool_scheduled_tasks
+SET last_run_at = :now, lease_until = :lease_until
+WHERE name = :name";
+
+const RENEW: &str = "UPDATE rocket_sqlite_rw_pool_scheduled_tasks SET lease_until = :lease_until
+WHERE name = :name AND last_run_at = :run_at";
+
+const FINISH: &str = "UPDATE rocket_sqlite_rw_pool_scheduled_tasks
+SET lease_until = NULL, last_error = :error
+WHERE name = :name AND last_run_at = :run_at";
+
+/// When a scheduled task runs. Runs which were missed (e.g. while no instance was
+/// running) are not made up for, beyond running the task once as soon as possible.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// A cron expression in UTC, with seconds: `sec min hour day-of-month month day-of-week [year]`.
+    /// The task first runs at the first time it matches after the task was first registered.
+    Cron(&'static str),
+    /// A fixed amount of time from the start of one run to the start of the next.
+    /// The task first runs as soon as it is registered.
+    Every(Duration),
+}
+
+/// A [`Schedule`], ready to be checked against.
+enum ParsedSchedule {
+    Cron(Box<cron::Schedule>),
+    Every(Duration),
+}
+
+impl ParsedSchedule {
+    fn parse(schedule: Schedule) -> Result<Self, cron::error::Error> {
+        Ok(match schedule {
+            Schedule::Cron(expression) => {
+                Self::Cron(Box::new(cron::Schedule::from_str(expression)?))
+            }
+            Schedule::Every(interval) => Self::Every(interval),
+        })
+    }
+
+    /// Whether a task which last ran at the given time (or was registered at the
+    /// given time, if it never ran) should run now.
+    fn is_due(&self, last_run_at: Option<i64>, created_at: i64, now: i64) -> bool {
+        match self {
+            Self::Cron(schedule) => {
+                let Some(since) = Utc
+                    .timestamp_millis_opt(last_run_at.unwrap_or(created_at))
+                    .single()
+                else {
+                    return false;
+                };
+                schedule
+                    .after(&since)
+                    .next()
+                    .is_some_and(|next| next.timestamp_millis() <= now)
+            }
+            Self::Every(interval) => {
+                let interval = i64::try_from(interval.as_millis()).unwrap_or(i64::MAX);
+                last_run_at.is_none_or(|last_run_at| last_run_at.saturating_add(interval) <= now)
+            }
+        }
+    }
+}
+
+/// The future returned by a [`ScheduledTaskFn`]. If it fails, the error is logged
+/// and recorded, and the task runs again on its next scheduled run.
+pub type ScheduledTaskFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+
+/// Function to run for a scheduled task.
+pub type ScheduledTaskFn<DB> = for<'a> fn(AuthorizedConnector<'a, DB>) -> ScheduledTaskFuture<'a>;
+
+/// Wrapper for a [`ScheduledTaskFn`], along with its name and when it runs.
+/// The name identifies the task in the database, so should not change.
+pub struct ScheduledTask<DB> {
+    pub name: &'static str,
+    pub schedule: Schedule,
+    pub task: ScheduledTaskFn<DB>,
+}
+
+impl<DB> ScheduledTask<DB> {
+    pub const fn new(name: &'static str, schedule: Schedule, task: ScheduledTaskFn<DB>) -> Self {
+        Self {
+            name,
+            schedule,
+            task,
+        }
+    }
+}
+
+impl<DB> Clone for ScheduledTask<DB> {
+    fn clone(&self) -> Self {
+        Self::new(self.name, self.schedule, self.task)
+    }
+}
+
+/// Which of the tasks are due to run at the given time.
+fn due(
+    connection: &Connection,
+    schedules: &HashMap<&'static str, ParsedSchedule>,
+    names: &str,
+    now: i64,
+) -> Result<Vec<&'static str>, rusqlite::Error> {
+    let mut statement = connection.prepare_cached(IDLE)?;
+    let idle = statement
+        .query_map(named_params! { ":names": names, ":now": now }, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(idle
+        .into_iter()
+        .filter_map(|(name, last_run_at, created_at)| {
+            let (name, schedule) = schedules.get_key_value(name.as_str())?;
+            schedule
+                .is_due(last_run_at, created_at, now)
+                .then_some(*name)
+        })
+        .collect())
+}
+
+/// Claim whichever of the tasks are due to run, returning their names and when they were claimed.
+fn claim(
+    transaction: &Transaction,
+    schedules: &HashMap<&'static str, ParsedSchedule>,
+    names: &str,
+    lease: Duration,
+) -> Result<Vec<(&'static str, i64)>, rusqlite::Error> {
+    let now = unix_millis(SystemTime::now());
+    let due = due(transaction, schedules, names, now)?;
+    for name in &due {
+        transaction.prepare_cached(CLAIM)?.execute(named_params! {
+            ":name": name,
+            ":now": now,
+            ":lease_until": unix_millis_after(lease),
+        })?;
+    }
+    Ok(due.into_iter().map(|name| (name, now)).collect())
+}
+
+/// Runs the scheduled tasks for a database.
+struct Runner<DB> {
+    pool: ConnectionPool<DB>,
+    tasks: HashMap<&'static str, ScheduledTaskFn<DB>>,
+    schedules: Arc<HashMap<&'static str, ParsedSchedule>>,
+    /// The names of the tasks, as a JSON array.
+    names: String,
+    config: SchedulerConfig,
+}
+
+impl<DB: 'static> Runner<DB> {
+    /// Run the given task, then record how it went.
+    async fn process(self: Arc<Self>, name: &'static str, run_at: i64) {
+        let task = *self
+            .tasks
+            .get(name)
+            .expect("internal invariant broken: only registered tasks are claimed");
+        let lease = Lease {
+            description: format!("scheduled task {name}"),
+            duration: Duration::from_millis(self.config.lease),
+            renew: RENEW,
+            key: vec![
+                (":name", name.to_owned().into()),
+                (":run_at", run_at.into()),
+            ],
+        };
+        let pool = self.pool.clone();
+        let result = lease
+            .run(&self.pool, async move {
+                task(pool.get().authorize(AUTHORIZATION)).await
+            })
+            .await;
+
+        let error = result.err().map(|e| {
+            rocket::error!(
+                "scheduled task {} on {} failed: {:#}",
+                name,
+                self.pool.name(),
+                e
+            );
+            format!("{e:#}")
+        });
+        // If this fails, the task can run again once its lease runs out.
+        if let Err(e) = lease
+            .execute(&self.pool, FINISH, vec![(":error", error.into())])
+            .await
+        {
+            rocket::error!(
+                "failed to record the outcome of scheduled task {} on {}: {}",
+                name,
+                self.pool.name(),
+                e
+            );
+        }
+    }
+
+    /// Claim whichever of the tasks are due to run. This is checked on a reader
+    /// first, so that the writer is only taken when there is something to claim.
+    async fn claim(&self) -> Result<Vec<(&'static str, i64)>, TransactionError<rusqlite::Error>> {
+        let schedules = Arc::clone(&self.schedules);
+        let names = self.names.clone();
+        let any_due = self
+            .pool
+            .connect_and_read(move |connection| {
+                due(
+                    connection,
+                    &schedules,
+                    &names,
+                    unix_millis(SystemTime::now()),
+                )
+                .map(|due| !due.is_empty())
+            })
+            .await
+            .and_then(|result| result.map_err(Error::from))?;
+        if !any_due {
+            return Ok(Vec::new());
+        }
+
+        let schedules = Arc::clone(&self.schedules);
+        let names = self.names.clone();
+        let lease = Duration::from_millis(self.config.lease);
+        self.pool
+            .try_connect_and_write(AUTHORIZATION, move |transaction| {
+                claim(transaction, &schedules, &names, lease)
+            })
+            .await
+    }
+
+    /// Claim and run tasks as they become due, until told to stop.
+    async fn run(self: Arc<Self>, mut stop: watch::Receiver<bool>) {
+        let poll_interval = Duration::from_millis(self.config.poll_interval);
+        loop {
+            match self.claim().await {
+                Ok(claimed) => {
+                    for (name, run_at) in claimed {
+                        tokio::spawn(Arc::clone(&self).process(name, run_at));
+                    }
+                }
+                Err(TransactionError::Database(Error::ShuttingDown)) => break,
+                Err(e) => {
+                    rocket::error!(
+                        "failed to claim scheduled tasks on {}: {}",
+                        self.pool.name(),
+                        e
+                    );
+                }
+            }
+            tokio::select! {
+                _ = stop.changed() => break,
+                () = tokio::time::sleep(poll_interval) => {}
+            }
+        }
+    }
+}
+
+/// The scheduled tasks for a database.
+pub struct Scheduler<DB> {
+    _marker: std::marker::PhantomData<fn() -> DB>,
+}
+
+impl<DB: Database> Scheduler<DB> {
+    /// Fairing which creates the table tracking the given tasks, and runs them on their
+    /// schedules while rocket is running. Each run of a task is claimed in the database
+    /// first, so that however many instances share it, only one runs the task at a time.
+    /// The database's own fairing must also be attached.
+    pub fn fairing(tasks: Vec<ScheduledTask<DB>>) -> impl Fairing {
+        background::fairing(
+            "Scheduler",
+            COMPONENT,
+            MIGRATIONS,
+            move |rocket, pool: ConnectionPool<DB>, config| async move {
+                let mut schedules = HashMap::new();
+                for task in &tasks {
+                    let schedule = match ParsedSchedule::parse(task.schedule) {
+                        Ok(schedule) => schedule,
+                        Err(e) => {
+                            rocket::error!(
+                                "invalid schedule for task {} on {}: {}",
+                                task.name,
+                                DB::NAME,
+                                e
+                            );
+                            return Err(rocket);
+                        }
+                    };
+                    if schedules.insert(task.name, schedule).is_some() {
+                        rocket::error!(
+                            "more than one scheduled task on {} is named {}",
+                            DB::NAME,
+                            task.name
+                        );
+                        return Err(rocket);
+                    }
+                }
+
+                let names: Vec<_> = tasks.iter().map(|task| task.name).collect();
+                let registered = pool
+                    .try_connect_and_write(AUTHORIZATION, move |transaction| {
+                        let now = unix_millis(SystemTime::now());
+                        let mut statement = transaction.prepare_cached(REGISTER)?;
+                        for name in names {
+                            statement.execute(named_params! { ":name": name, ":now": now })?;
+                        }
+                        Ok::<_, rusqlite::Error>(())
+                    })
+                    .await;
+                if let Err(e) = registered {
+                    rocket::error!(
+                        "failed to register the scheduled tasks for {}: {}",
+                        DB::NAME,
+                        e
+                    );
+                    return Err(rocket);
+                }
+
+                let names =
+                    serde_json::to_string(&tasks.iter().map(|task| task.name).collect::<Vec<_>>())
+                        .expect("internal invariant broken: strings always serialize");
+                let runner = Arc::new(Runner {
+                    pool,
+                    tasks: tasks
+                        .into_iter()
+                        .map(|task| (task.name, task.task))
+                        .collect(),
+                    schedules: Arc::new(schedules),
+                    names,
+                    config: config.scheduler,
+                });
+                Ok(background::spawn_worker(
+                    rocket,
+                    "Scheduler Runner",
+                    move |stop| runner.run(stop),
+                ))
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Milliseconds since the Unix epoch at the given time on 2024-01-01, in UTC.
+    fn at(hour: u32, minute: u32, second: u32) -> i64 {
+        Utc.with_ymd_and_hms(2024, 1, 1, hour, minute, second)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn cron(expression: &'static str) -> ParsedSchedule {
+        ParsedSchedule::parse(Schedule::Cron(expression)).unwrap()
+    }
+
+    fn every(interval: Duration) -> ParsedSchedule {
+        ParsedSchedule::parse(Schedule::Every(interval)).unwrap()
+    }
+
+    #[test]
+    fn interval_tasks_run_as_soon_as_they_are_registered() {
+        let schedule = every(Duration::from_secs(90));
+        assert!(schedule.is_due(None, at(10, 0, 0), at(10, 0, 0)));
+    }
+
+    #[test]
+    fn interval_tasks_run_once_the_interval_has_passed() {
+        let schedule = every(Duration::from_secs(90));
+        let last_run_at = Some(at(10, 0, 0));
+        assert!(!schedule.is_due(last_run_at, at(9, 0, 0), at(10, 1, 29)));
+        assert!(schedule.is_due(last_run_at, at(9, 0, 0), at(10, 1, 30)));
+        assert!(schedule.is_due(last_run_at, at(9, 0, 0), at(15, 0, 0)));
+    }
+
+    #[test]
+    fn cron_tasks_first_run_at_the_first_match_after_being_registered() {
+        let schedule = cron("0 0 * * * *");
+        assert!(!schedule.is_due(None, at(10, 30, 0), at(10, 30, 0)));
+        assert!(!schedule.is_due(None, at(10, 30, 0), at(10, 59, 59)));
+        assert!(schedule.is_due(None, at(10, 30, 0), at(11, 0, 0)));
+    }
+
+    #[test]
+    fn cron_tasks_run_at_the_next_match_after_their_last_run() {
+        let schedule = cron("0 0 * * * *");
+        let last_run_at = Some(at(11, 0, 0));
+        // The last run takes over from when the task was registered.
+        assert!(!schedule.is_due(last_run_at, at(10, 30, 0), at(11, 30, 0)));
+        assert!(schedule.is_due(last_run_at, at(10, 30, 0), at(12, 0, 0)));
+    }
+
+    #[test]
+    fn missed_cron_runs_are_only_made_up_for_once() {
+        let schedule = cron("0 0 * * * *");
+        // Several runs were missed, so the task runs straight away...
+        assert!(schedule.is_due(Some(at(11, 0, 0)), at(10, 0, 0), at(15, 20, 0)));
+        // ...and then not again until the next match.
+        assert!(!schedule.is_due(Some(at(15, 20, 0)), at(10, 0, 0), at(15, 59, 59)));
+        assert!(schedule.is_due(Some(at(15, 20, 0)), at(10, 0, 0), at(16, 0, 0)));
+    }
+
+    #[test]
+    fn cron_tasks_which_never_match_again_are_never_due() {
+        let schedule = cron("0 0 0 1 1 * 2023");
+        assert!(!schedule.is_due(None, at(10, 0, 0), at(23, 59, 59)));
+    }
+
+    #[test]
+    fn invalid_cron_expressions_are_rejected() {
+        assert!(ParsedSchedule::parse(Schedule::Cron("every hour")).is_err());
+    }
+}
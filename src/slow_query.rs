@@ -0,0 +1,99 @@
+use crate::config::SlowQueryConfig;
+
+use std::{
+    cell::Cell,
+    ffi::{c_int, c_uint, c_void, CStr},
+    time::Duration,
+};
+
+use rusqlite::{ffi, Connection};
+
+/// State for logging the slow queries run on a single connection.
+struct SlowQueryLog {
+    database: &'static str,
+    role: &'static str,
+    threshold: Duration,
+    sample_rate: f64,
+    /// Accumulates the sample rate for every slow query, which is logged
+    /// whenever this reaches 1. Only touched from the connection's callbacks.
+    sampled: Cell<f64>,
+}
+
+impl SlowQueryLog {
+    /// Whether the next slow query should be logged.
+    fn sample(&self) -> bool {
+        let sampled = self.sampled.get() + self.sample_rate;
+        if sampled >= 1.0 {
+            self.sampled.set(sampled - 1.0);
+            true
+        } else {
+            self.sampled.set(sampled);
+            false
+        }
+    }
+}
+
+/// Callback registered with `sqlite3_trace_v2`, which owns the log until the connection closes.
+unsafe extern "C" fn trace(
+    event: c_uint,
+    context: *mut c_void,
+    statement: *mut c_void,
+    elapsed: *mut c_void,
+) -> c_int {
+    let log = context.cast::<SlowQueryLog>();
+    match c_int::try_from(event) {
+        Ok(ffi::SQLITE_TRACE_PROFILE) => {
+            let log = &*log;
+            let elapsed =
+                Duration::from_nanos(u64::try_from(*elapsed.cast::<i64>()).unwrap_or_default());
+            if elapsed < log.threshold || !log.sample() {
+                return 0;
+            }
+            let sql = ffi::sqlite3_expanded_sql(statement.cast());
+            if sql.is_null() {
+                return 0;
+            }
+            rocket::warn!(
+                "slow query on {} {} took {:?}: {}",
+                log.database,
+                log.role,
+                elapsed,
+                CStr::from_ptr(sql).to_string_lossy()
+            );
+            ffi::sqlite3_free(sql.cast());
+        }
+        Ok(ffi::SQLITE_TRACE_CLOSE) => drop(Box::from_raw(log)),
+        _ => {}
+    }
+    0
+}
+
+/// Log queries run on the given connection which take longer than the configured threshold.
+pub fn install(
+    connection: &Connection,
+    database: &'static str,
+    is_write: bool,
+    config: &SlowQueryConfig,
+) -> Result<(), rusqlite::Error> {
+    let log = Box::into_raw(Box::new(SlowQueryLog {
+        database,
+        role: if is_write { "writer" } else { "reader" },
+        threshold: Duration::from_millis(config.threshold),
+        sample_rate: config.sample_rate,
+        sampled: Cell::new(0.0),
+    }));
+    #[allow(clippy::cast_sign_loss)]
+    let mask = (ffi::SQLITE_TRACE_PROFILE | ffi::SQLITE_TRACE_CLOSE) as c_uint;
+    // SAFETY: the log is only freed by the callback, once the connection closes.
+    let result =
+        unsafe { ffi::sqlite3_trace_v2(connection.handle(), mask, Some(trace), log.cast()) };
+    if result != ffi::SQLITE_OK {
+        // SAFETY: the callback was not registered, so nothing else owns the log.
+        drop(unsafe { Box::from_raw(log) });
+        return Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(result),
+            None,
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use crate::manager::ConnectionManager;
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use r2d2::Pool;
+
+/// Upper bounds of the buckets used by latency histograms.
+const BUCKETS: [Duration; 11] = [
+    Duration::from_micros(100),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+/// Snapshot of a latency histogram.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// The number of observations in each bucket, along with the bucket's
+    /// (inclusive) upper bound. The last bucket has no upper bound.
+    pub buckets: Vec<(Option<Duration>, u64)>,
+    /// The total number of observations.
+    pub count: u64,
+    /// The sum of all observations.
+    pub sum: Duration,
+}
+
+/// Snapshot of the statistics for one side (readers or the writer) of a pool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// The maximum number of connections.
+    pub max_connections: u32,
+    /// The number of open connections, both idle and in use.
+    pub connections: u32,
+    /// The number of idle connections.
+    pub idle_connections: u32,
+    /// The number of connections currently handed out.
+    pub in_use: usize,
+    /// The number of tasks currently waiting for a connection permit.
+    pub waiting: usize,
+    /// The total number of connections handed out.
+    pub acquired: u64,
+    /// The total number of times waiting for a connection permit timed out.
+    pub permit_timeouts: u64,
+    /// The total number of times checking out a connection from the underlying pool failed.
+    pub checkout_failures: u64,
+    /// The total number of calls whose queries were aborted for exceeding the statement timeout.
+    pub statement_timeouts: u64,
+    /// Time spent waiting for a connection permit.
+    pub permit_wait: LatencyHistogram,
+    /// Time spent checking out a connection from the underlying pool.
+    pub checkout: LatencyHistogram,
+    /// Time connections were held for before being released.
+    pub held: LatencyHistogram,
+    /// Time transactions were open for, from beginning until being committed or rolled back.
+    pub transactions: LatencyHistogram,
+}
+
+/// Snapshot of the statistics for a [`crate::ConnectionPool`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Statistics for the read-only connections.
+    pub readers: ConnectionStats,
+    /// Statistics for the write connection.
+    pub writer: ConnectionStats,
+}
+
+/// Histogram of durations, which can be updated concurrently.
+#[derive(Default)]
+struct Histogram {
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, duration: Duration) {
+        let index = BUCKETS
+            .iter()
+            .position(|bound| duration <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn snapshot(&self) -> LatencyHistogram {
+        let buckets: Vec<_> = BUCKETS
+            .iter()
+            .copied()
+            .map(Some)
+            .chain(std::iter::once(None))
+            .zip(&self.counts)
+            .map(|(bound, count)| (bound, count.load(Ordering::Relaxed)))
+            .collect();
+        let count = buckets.iter().map(|(_, count)| count).sum();
+        LatencyHistogram {
+            buckets,
+            count,
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Decrements the waiting count when dropped, so that cancelled waits are accounted for.
+pub struct Waiting<'a>(&'a StatsRecorder);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Cumulative statistics for one side of a pool.
+#[derive(Default)]
+pub struct StatsRecorder {
+    in_use: AtomicUsize,
+    waiting: AtomicUsize,
+    acquired: AtomicU64,
+    permit_timeouts: AtomicU64,
+    checkout_failures: AtomicU64,
+    statement_timeouts: AtomicU64,
+    permit_wait: Histogram,
+    checkout: Histogram,
+    held: Histogram,
+    transactions: Histogram,
+}
+
+/// Records how long a transaction was open for when dropped, however the transaction ended.
+/// It should be dropped as soon as the transaction has been committed or rolled back.
+pub struct TransactionTimer {
+    stats: Arc<StatsRecorder>,
+    began_at: Instant,
+}
+
+impl Drop for TransactionTimer {
+    fn drop(&mut self) {
+        self.stats.transactions.record(self.began_at.elapsed());
+    }
+}
+
+impl StatsRecorder {
+    /// Start waiting for a connection permit.
+    pub fn wait(&self) -> Waiting<'_> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        Waiting(self)
+    }
+
+    /// Record the outcome of waiting for a connection permit.
+    pub fn record_permit_wait(&self, duration: Duration, timed_out: bool) {
+        self.permit_wait.record(duration);
+        if timed_out {
+            self.permit_timeouts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record the outcome of checking out a connection from the underlying pool.
+    pub fn record_checkout(&self, duration: Duration, succeeded: bool) {
+        self.checkout.record(duration);
+        if succeeded {
+            self.acquired.fetch_add(1, Ordering::Relaxed);
+            self.in_use.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.checkout_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record queries being aborted for exceeding the statement timeout.
+    pub fn record_statement_timeout(&self) {
+        self.statement_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a connection being released after being held for the given duration.
+    pub fn record_release(&self, held: Duration) {
+        self.held.record(held);
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Start timing a transaction which has just begun.
+    pub fn time_transaction(self: &Arc<Self>) -> TransactionTimer {
+        TransactionTimer {
+            stats: Arc::clone(self),
+            began_at: Instant::now(),
+        }
+    }
+
+    /// Take a snapshot of the statistics, along with the state of the underlying pool.
+    pub fn snapshot(&self, pool: Option<&Pool<ConnectionManager>>) -> ConnectionStats {
+        let (max_connections, connections, idle_connections) = pool.map_or((0, 0, 0), |pool| {
+            let state = pool.state();
+            (pool.max_size(), state.connections, state.idle_connections)
+        });
+        ConnectionStats {
+            max_connections,
+            connections,
+            idle_connections,
+            in_use: self.in_use.load(Ordering::Relaxed),
+            waiting: self.waiting.load(Ordering::Relaxed),
+            acquired: self.acquired.load(Ordering::Relaxed),
+            permit_timeouts: self.permit_timeouts.load(Ordering::Relaxed),
+            checkout_failures: self.checkout_failures.load(Ordering::Relaxed),
+            statement_timeouts: self.statement_timeouts.load(Ordering::Relaxed),
+            permit_wait: self.permit_wait.snapshot(),
+            checkout: self.checkout.snapshot(),
+            held: self.held.snapshot(),
+            transactions: self.transactions.snapshot(),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Helpers for testing applications which use this crate.
+
+use crate::{ConnectionPool, Database, WriteAuthorization};
+
+use std::{
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use rocket::{local::asynchronous::Client, Build, Rocket};
+use tempfile::TempDir;
+
+/// SQL to run once migrations have been applied.
+enum Fixture {
+    Sql(String),
+    File(PathBuf),
+}
+
+impl Fixture {
+    fn load(self) -> Result<String> {
+        match self {
+            Self::Sql(sql) => Ok(sql),
+            Self::File(path) => std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read fixture {}", path.display())),
+        }
+    }
+}
+
+/// An isolated database in a temporary directory, with migrations applied, and
+/// a local client for a rocket instance using it.
+///
+/// The files are removed when this is dropped, though the pool's connections may
+/// still be closing in the background by then; use [`TestDatabase::close`] to wait
+/// for them first.
+pub struct TestDatabase<DB> {
+    client: Client,
+    path: PathBuf,
+    _dir: TempDir,
+    _marker: PhantomData<fn() -> DB>,
+}
+
+impl<DB: Database> TestDatabase<DB> {
+    /// Create a test database for a plain rocket instance, without any fixtures.
+    pub async fn new() -> Result<Self> {
+        Self::builder().build().await
+    }
+
+    /// Start building a test database.
+    pub const fn builder() -> TestDatabaseBuilder<DB> {
+        TestDatabaseBuilder {
+            rocket: None,
+            fixtures: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// The client for the rocket instance using the database.
+    pub const fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The pool for the database.
+    pub fn pool(&self) -> &ConnectionPool<DB> {
+        ConnectionPool::get_pool(self.client.rocket())
+            .expect("internal invariant broken: the pool exists once built")
+    }
+
+    /// The path of the database file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Shut the pool down, waiting for its connections to be closed, then remove the files.
+    pub async fn close(self) {
+        self.pool().shutdown().await;
+    }
+}
+
+/// Builder for a [`TestDatabase`].
+pub struct TestDatabaseBuilder<DB> {
+    rocket: Option<Rocket<Build>>,
+    fixtures: Vec<Fixture>,
+    _marker: PhantomData<fn() -> DB>,
+}
+
+impl<DB: Database> TestDatabaseBuilder<DB> {
+    /// Use the given rocket instance (e.g. with routes mounted) instead of a plain one.
+    /// The database's fairing is attached to it, so it should not be attached already.
+    #[must_use]
+    pub fn rocket(mut self, rocket: Rocket<Build>) -> Self {
+        self.rocket = Some(rocket);
+        self
+    }
+
+    /// Run the given SQL once migrations have been applied.
+    /// Fixtures run in the order they were added, in a single transaction.
+    #[must_use]
+    pub fn fixture(mut self, sql: impl Into<String>) -> Self {
+        self.fixtures.push(Fixture::Sql(sql.into()));
+        self
+    }
+
+    /// Run the SQL in the given file once migrations have been applied.
+    #[must_use]
+    pub fn fixture_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.fixtures.push(Fixture::File(path.into()));
+        self
+    }
+
+    /// Create the database, launch the rocket instance and load the fixtures.
+    pub async fn build(self) -> Result<TestDatabase<DB>> {
+        let fixtures = self
+            .fixtures
+            .into_iter()
+            .map(Fixture::load)
+            .collect::<Result<Vec<_>>>()?;
+
+        let dir = tempfile::Builder::new()
+            .prefix(DB::NAME)
+            .tempdir()
+            .context("failed to create a temporary directory")?;
+        let path = dir.path().join(format!("{}.sqlite", DB::NAME));
+        let rocket = self.rocket.unwrap_or_else(rocket::build);
+        let figment = rocket.figment().clone().merge((
+            format!("databases.{}.url", DB::NAME),
+            path.display().to_string(),
+        ));
+        let client = Client::tracked(DB::attach(rocket.configure(figment)))
+            .await
+            .map_err(|e| anyhow!("failed to launch rocket: {e}"))?;
+        if ConnectionPool::<DB>::get_pool(client.rocket()).is_none() {
+            return Err(anyhow!("the pool for {} was not created", DB::NAME));
+        }
+
+        let database = TestDatabase {
+            client,
+            path,
+            _dir: dir,
+            _marker: PhantomData,
+        };
+        if !fixtures.is_empty() {
+            database
+                .pool()
+                .connect_and_write(
+                    WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest,
+                    move |transaction| {
+                        for sql in &fixtures {
+                            transaction.execute_batch(sql)?;
+                        }
+                        transaction.commit()
+                    },
+                )
+                .await?
+                .context("failed to load fixtures")?;
+        }
+        Ok(database)
+    }
+}
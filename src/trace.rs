@@ -0,0 +1,124 @@
+// Spans for instrumenting the pool. Without the `tracing` feature, these are
+// stand-ins which do nothing, so that callers don't need to care.
+
+#[cfg(feature = "tracing")]
+mod spans {
+    use crate::WriteAuthorization;
+
+    pub use tracing::{Instrument, Span};
+
+    /// Span covering the time a connection is held for.
+    pub fn connection(
+        database: &'static str,
+        role: &'static str,
+        authorization: Option<&WriteAuthorization>,
+    ) -> Span {
+        tracing::info_span!(
+            "sqlite.connection",
+            database,
+            role,
+            authorization = authorization.map(tracing::field::debug)
+        )
+    }
+
+    /// Span covering the wait for a connection permit.
+    pub fn acquire_permit(connection: &Span) -> Span {
+        tracing::info_span!(parent: connection, "sqlite.acquire_permit")
+    }
+
+    /// Span covering checking out a connection from the underlying pool.
+    pub fn checkout(connection: &Span) -> Span {
+        tracing::info_span!(parent: connection, "sqlite.checkout")
+    }
+
+    /// Span covering beginning a transaction.
+    pub fn begin() -> Span {
+        tracing::info_span!("sqlite.begin")
+    }
+
+    /// Span covering committing a transaction.
+    pub fn commit() -> Span {
+        tracing::info_span!("sqlite.commit")
+    }
+
+    /// Span covering rolling back a transaction.
+    pub fn rollback() -> Span {
+        tracing::info_span!("sqlite.rollback")
+    }
+
+    /// Span covering running a single statement.
+    pub fn statement(sql: &str) -> Span {
+        tracing::info_span!("sqlite.statement", sql)
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod spans {
+    use crate::WriteAuthorization;
+
+    /// Stand-in for `tracing::Span`.
+    #[derive(Clone, Debug)]
+    pub struct Span;
+
+    /// Stand-in for the guard returned when entering a `tracing::Span`.
+    pub struct Entered;
+
+    // Mirrors the methods of `tracing::Span` which are used.
+    #[allow(clippy::unused_self)]
+    impl Span {
+        pub const fn enter(&self) -> Entered {
+            Entered
+        }
+
+        pub const fn entered(self) -> Entered {
+            Entered
+        }
+
+        pub fn in_scope<F: FnOnce() -> T, T>(&self, f: F) -> T {
+            f()
+        }
+    }
+
+    /// Stand-in for `tracing::Instrument`.
+    pub trait Instrument: Sized {
+        fn instrument(self, _span: Span) -> Self {
+            self
+        }
+    }
+
+    impl<T> Instrument for T {}
+
+    pub const fn connection(
+        _database: &'static str,
+        _role: &'static str,
+        _authorization: Option<&WriteAuthorization>,
+    ) -> Span {
+        Span
+    }
+
+    pub const fn acquire_permit(_connection: &Span) -> Span {
+        Span
+    }
+
+    pub const fn checkout(_connection: &Span) -> Span {
+        Span
+    }
+
+    pub const fn begin() -> Span {
+        Span
+    }
+
+    pub const fn commit() -> Span {
+        Span
+    }
+
+    pub const fn rollback() -> Span {
+        Span
+    }
+
+    pub const fn statement(_sql: &str) -> Span {
+        Span
+    }
+}
+
+pub use spans::*;
@@ -1,3 +1,5 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 /// A wrapper around [`spawn_blocking`] that propagates panics to the calling code.
 pub async fn run_blocking<F, R>(job: F) -> R
 where
@@ -12,3 +14,16 @@ where
         ),
     }
 }
+
+/// Milliseconds since the Unix epoch.
+pub fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| {
+        i64::try_from(since.as_millis()).unwrap_or(i64::MAX)
+    })
+}
+
+/// Milliseconds since the Unix epoch, some amount of time from now.
+pub fn unix_millis_after(duration: Duration) -> i64 {
+    unix_millis(SystemTime::now())
+        .saturating_add(i64::try_from(duration.as_millis()).unwrap_or(i64::MAX))
+}
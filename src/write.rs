@@ -1,19 +1,30 @@
-use crate::{holder::ConnectionHolder, ConnectionPool, Error, WriteAuthorization};
+use crate::{
+    config::RetryConfig, holder::ConnectionHolder, manager::ConnectionManager, trace,
+    ConnectionPool, Error, TransactionError, WriteAuthorization,
+};
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, ops::ControlFlow, sync::Arc, time::Duration};
 
 use r2d2::PooledConnection;
-use r2d2_sqlite::SqliteConnectionManager;
 use rocket::{
     http::Status,
     outcome::IntoOutcome,
     request::{FromRequest, Outcome, Request},
 };
-use rusqlite::{Transaction, TransactionBehavior};
+use rusqlite::{ErrorCode, Transaction, TransactionBehavior};
+
+/// Whether the given error means another connection holds a conflicting lock.
+fn is_busy(error: &rusqlite::Error) -> bool {
+    matches!(
+        error.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
 
 /// A write connection to the database.
 pub struct WriteConnection<DB> {
     holder: ConnectionHolder,
+    retry: RetryConfig,
     _marker: PhantomData<fn() -> DB>,
 }
 
@@ -21,27 +32,203 @@ impl<DB> From<ConnectionHolder> for WriteConnection<DB> {
     fn from(holder: ConnectionHolder) -> Self {
         Self {
             holder,
+            retry: RetryConfig::default(),
             _marker: PhantomData,
         }
     }
 }
 
+impl<DB> WriteConnection<DB> {
+    /// Use the given configuration when retrying transactions.
+    pub(crate) const fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+}
+
 impl<DB: 'static> WriteConnection<DB> {
     /// Run the provided function against the connection inside a transaction
     #[inline]
-    pub async fn run<F, R>(&self, f: F) -> R
+    pub async fn run<F, R>(&self, f: F) -> Result<R, Error>
     where
-        F: FnOnce(Transaction) -> R + Send,
-        R: Send,
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
     {
-        let with_transaction = move |connection: &mut PooledConnection<SqliteConnectionManager>| {
-            // TODO: Better error handling
-            let transaction = connection
-                .transaction_with_behavior(TransactionBehavior::Immediate)
-                .expect("internal invariant broken: couldn't create a transaction");
-            f(transaction)
+        self.run_in_transaction(self.holder.statement_timeout, f)
+            .await
+    }
+
+    /// Run the provided function against the connection inside a transaction, aborting
+    /// its queries (and returning [`Error::StatementTimeout`]) if they run for longer than
+    /// the given time, instead of the configured statement timeout.
+    pub async fn run_with_statement_timeout<F, R>(
+        &self,
+        statement_timeout: Duration,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.run_in_transaction(Some(statement_timeout), f).await
+    }
+
+    async fn run_in_transaction<F, R>(
+        &self,
+        statement_timeout: Option<Duration>,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let stats = Arc::clone(&self.holder.stats);
+        let with_transaction = move |connection: &mut PooledConnection<ConnectionManager>| {
+            let transaction = trace::begin()
+                .in_scope(|| connection.transaction_with_behavior(TransactionBehavior::Immediate))
+                .map_err(Error::TransactionBegin)?;
+            // The function commits or rolls back the transaction by the time it returns.
+            let _timer = stats.time_transaction();
+            Ok(f(transaction))
         };
-        self.holder.run(with_transaction).await
+        self.holder
+            .run_with_statement_timeout(statement_timeout, with_transaction)
+            .await?
+    }
+
+    /// Run the provided function against the connection inside a transaction, interrupting
+    /// it (and returning [`Error::Interrupted`]) if it doesn't finish within the given time.
+    /// The transaction is rolled back if it is interrupted before being committed.
+    pub async fn run_with_timeout<F, R>(&self, timeout: Duration, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(Transaction) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        tokio::time::timeout(timeout, self.run(f))
+            .await
+            .map_err(|_| Error::Interrupted)?
+    }
+
+    /// Run the provided function against the connection inside a transaction, committing
+    /// it if the function returns `Ok` and rolling it back if it returns `Err`.
+    pub async fn try_run<F, R, E>(&self, f: F) -> Result<R, TransactionError<E>>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        let stats = Arc::clone(&self.holder.stats);
+        self.holder
+            .run(move |connection| {
+                let transaction = trace::begin()
+                    .in_scope(|| {
+                        connection.transaction_with_behavior(TransactionBehavior::Immediate)
+                    })
+                    .map_err(Error::TransactionBegin)?;
+                let _timer = stats.time_transaction();
+                match f(&transaction) {
+                    Ok(value) => {
+                        trace::commit()
+                            .in_scope(|| transaction.commit())
+                            .map_err(Error::Rusqlite)?;
+                        Ok(value)
+                    }
+                    Err(e) => {
+                        // Dropping the transaction rolls it back.
+                        trace::rollback().in_scope(|| drop(transaction));
+                        Err(TransactionError::Aborted(e))
+                    }
+                }
+            })
+            .await?
+    }
+
+    /// Run the provided function against the connection inside a transaction, committing
+    /// it if the function returns `Ok`. If the database is busy or locked (e.g. by another
+    /// process), the transaction is rolled back and retried with exponential backoff, so
+    /// the function may be called more than once. The writer is held while backing off,
+    /// so other writes in this process wait too; [`ConnectionPool::connect_and_write_with_retry`]
+    /// releases it between attempts instead.
+    pub async fn run_with_retry<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: Fn(&Transaction) -> Result<R, rusqlite::Error> + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        let f = Arc::new(f);
+        let mut retry = Retry::new(&self.retry);
+        loop {
+            match retry.next(self.attempt(Arc::clone(&f)).await) {
+                ControlFlow::Continue(backoff) => tokio::time::sleep(backoff).await,
+                ControlFlow::Break(result) => return result,
+            }
+        }
+    }
+
+    /// Make a single attempt at a transaction which is retried if the database is busy.
+    pub(crate) async fn attempt<F, R>(&self, f: Arc<F>) -> Result<R, Error>
+    where
+        F: Fn(&Transaction) -> Result<R, rusqlite::Error> + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        let stats = Arc::clone(&self.holder.stats);
+        self.holder
+            .run(move |connection| {
+                let transaction = trace::begin()
+                    .in_scope(|| {
+                        connection.transaction_with_behavior(TransactionBehavior::Immediate)
+                    })
+                    .map_err(Error::TransactionBegin)?;
+                let _timer = stats.time_transaction();
+                match f(&transaction) {
+                    Ok(value) => {
+                        trace::commit().in_scope(|| transaction.commit())?;
+                        Ok(value)
+                    }
+                    Err(e) => {
+                        trace::rollback().in_scope(|| drop(transaction));
+                        Err(e.into())
+                    }
+                }
+            })
+            .await
+            .and_then(|result| result)
+    }
+}
+
+/// Keeps track of the attempts made at a transaction which is retried while the database is busy.
+pub struct Retry {
+    max_attempts: u32,
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Retry {
+    pub fn new(config: &RetryConfig) -> Self {
+        let max_backoff = Duration::from_millis(config.max_backoff);
+        Self {
+            max_attempts: config.max_attempts,
+            attempts: 1,
+            backoff: Duration::from_millis(config.initial_backoff).min(max_backoff),
+            max_backoff,
+        }
+    }
+
+    /// Given the result of an attempt, either how long to wait before the next
+    /// attempt, or the result to return if there shouldn't be another.
+    pub fn next<R>(&mut self, result: Result<R, Error>) -> ControlFlow<Result<R, Error>, Duration> {
+        match result {
+            Err(Error::TransactionBegin(e) | Error::Rusqlite(e)) if is_busy(&e) => {
+                if self.attempts >= self.max_attempts {
+                    return ControlFlow::Break(Err(Error::RetriesExhausted(self.attempts, e)));
+                }
+                let backoff = self.backoff;
+                self.backoff = (self.backoff * 2).min(self.max_backoff);
+                self.attempts += 1;
+                ControlFlow::Continue(backoff)
+            }
+            result => ControlFlow::Break(result),
+        }
     }
 }
 
@@ -0,0 +1,110 @@
+use rocket::figment::{util::map, Figment};
+use rocket_sqlite_rw_pool::{
+    define_database, testing::TestDatabase, Change, ChangeOperation, Nested, WriteAuthorization,
+};
+use tokio::sync::broadcast::{error::TryRecvError, Receiver};
+
+define_database!(Db, "db", "tests/migrations");
+
+const AUTHORIZATION: WriteAuthorization =
+    WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest;
+
+fn figment() -> Figment {
+    rocket::Config::figment().merge(("log_level", "off"))
+}
+
+async fn database(figment: Figment) -> TestDatabase<Db> {
+    TestDatabase::builder()
+        .rocket(rocket::custom(figment))
+        .build()
+        .await
+        .unwrap()
+}
+
+fn insert(transaction: &rusqlite::Connection, name: &str) -> rusqlite::Result<i64> {
+    transaction.execute("INSERT INTO items (name) VALUES (?)", [name])?;
+    Ok(transaction.last_insert_rowid())
+}
+
+/// The rowids of the inserts received so far.
+fn received(changes: &mut Receiver<Change>) -> Vec<i64> {
+    let mut rowids = Vec::new();
+    loop {
+        match changes.try_recv() {
+            Ok(change) => {
+                assert_eq!(change.table, "items");
+                assert_eq!(change.operation, ChangeOperation::Insert);
+                rowids.push(change.rowid);
+            }
+            Err(TryRecvError::Empty) => return rowids,
+            Err(e) => panic!("failed to receive changes: {e}"),
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn rolled_back_nested_changes_are_not_delivered() {
+    let db = database(figment()).await;
+    let mut changes = db.pool().subscribe_changes();
+    let kept = db
+        .pool()
+        .connect_and_write(AUTHORIZATION, |transaction| {
+            let kept = insert(&transaction, "kept")?;
+            let released = transaction.nested(|transaction| insert(transaction, "released"))?;
+            let rolled_back = transaction.nested(|transaction| {
+                insert(transaction, "rolled back")?;
+                Err::<(), _>(rusqlite::Error::QueryReturnedNoRows)
+            });
+            assert!(rolled_back.is_err());
+            transaction.commit()?;
+            Ok::<_, rusqlite::Error>(vec![kept, released])
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received(&mut changes), kept);
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn rolled_back_grouped_changes_are_not_delivered() {
+    let db = database(figment().merge((
+        "databases.db.group_commit",
+        map!["max_batch_size" => 10, "max_linger" => 50],
+    )))
+    .await;
+    let mut changes = db.pool().subscribe_changes();
+    let pool = db.pool().clone();
+    let failed = tokio::spawn(async move {
+        pool.connect_and_write_grouped(AUTHORIZATION, |transaction| {
+            insert(transaction, "failed")?;
+            Err::<(), _>(rusqlite::Error::QueryReturnedNoRows)
+        })
+        .await
+    });
+    let kept = db
+        .pool()
+        .connect_and_write_grouped(AUTHORIZATION, |transaction| insert(transaction, "kept"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(failed.await.unwrap().unwrap().is_err());
+    assert_eq!(received(&mut changes), [kept]);
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn rolled_back_transactions_are_not_delivered() {
+    let db = database(figment()).await;
+    let mut changes = db.pool().subscribe_changes();
+    db.pool()
+        .connect_and_write(AUTHORIZATION, |transaction| {
+            insert(&transaction, "rolled back")?;
+            transaction.rollback()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(received(&mut changes).is_empty());
+    db.close().await;
+}
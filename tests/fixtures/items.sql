@@ -0,0 +1 @@
+INSERT INTO items (name) VALUES ('from a file');
@@ -0,0 +1,134 @@
+use rocket::figment::util::map;
+use rocket_sqlite_rw_pool::{
+    define_database, testing::TestDatabase, ConnectionPool, Error, WriteAuthorization,
+};
+use rusqlite::Transaction;
+use tokio::task::JoinHandle;
+
+define_database!(Db, "db", "tests/migrations");
+
+const AUTHORIZATION: WriteAuthorization =
+    WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest;
+
+/// A database which commits batches of the given size, waiting long enough for
+/// each batch to fill up.
+async fn database(batch_size: usize) -> TestDatabase<Db> {
+    let figment = rocket::Config::figment()
+        .merge(("log_level", "off"))
+        .merge((
+            "databases.db.group_commit",
+            map!["max_batch_size" => batch_size, "max_linger" => 5000],
+        ));
+    TestDatabase::builder()
+        .rocket(rocket::custom(figment))
+        .build()
+        .await
+        .unwrap()
+}
+
+/// Submit a grouped write in the background.
+fn submit<F, R>(
+    pool: &ConnectionPool<Db>,
+    f: F,
+) -> JoinHandle<Result<Result<R, rusqlite::Error>, Error>>
+where
+    F: FnOnce(&Transaction) -> Result<R, rusqlite::Error> + Send + 'static,
+    R: Send + 'static,
+{
+    let pool = pool.clone();
+    tokio::spawn(async move { pool.connect_and_write_grouped(AUTHORIZATION, f).await })
+}
+
+fn insert(transaction: &Transaction, name: &str) -> rusqlite::Result<usize> {
+    transaction.execute("INSERT INTO items (name) VALUES (?)", [name])
+}
+
+async fn names(db: &TestDatabase<Db>) -> Vec<String> {
+    db.pool()
+        .connect_and_read(|connection| {
+            connection
+                .prepare("SELECT name FROM items ORDER BY name")?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()
+        })
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn failed_write_only_rolls_back_its_own_savepoint() {
+    let db = database(3).await;
+    let first = submit(db.pool(), |transaction| insert(transaction, "first"));
+    let failed = submit(db.pool(), |transaction| {
+        insert(transaction, "failed")?;
+        insert(transaction, "failed")
+    });
+    let last = submit(db.pool(), |transaction| insert(transaction, "last"));
+
+    assert_eq!(first.await.unwrap().unwrap().unwrap(), 1);
+    let failed = failed.await.unwrap().unwrap().unwrap_err();
+    assert_eq!(
+        failed.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::ConstraintViolation)
+    );
+    assert_eq!(last.await.unwrap().unwrap().unwrap(), 1);
+    assert_eq!(names(&db).await, ["first", "last"]);
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn panicking_write_does_not_poison_the_batch() {
+    let db = database(3).await;
+    let first = submit(db.pool(), |transaction| insert(transaction, "first"));
+    let panicked = submit(db.pool(), |transaction| -> rusqlite::Result<usize> {
+        insert(transaction, "panicked")?;
+        panic!("grouped write panicked");
+    });
+    let last = submit(db.pool(), |transaction| insert(transaction, "last"));
+
+    assert_eq!(first.await.unwrap().unwrap().unwrap(), 1);
+    // The panic is passed on to the caller.
+    assert!(panicked.await.unwrap_err().is_panic());
+    assert_eq!(last.await.unwrap().unwrap().unwrap(), 1);
+    assert_eq!(names(&db).await, ["first", "last"]);
+
+    // The queue carries on afterwards.
+    let after = submit(db.pool(), |transaction| insert(transaction, "after"));
+    submit(db.pool(), |transaction| insert(transaction, "after again"));
+    submit(db.pool(), |transaction| insert(transaction, "and again"));
+    assert_eq!(after.await.unwrap().unwrap().unwrap(), 1);
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn failed_commit_reaches_every_caller() {
+    let db = database(3).await;
+    let first = submit(db.pool(), |transaction| insert(transaction, "first"));
+    // Only checked once the batch commits.
+    let dangling = submit(db.pool(), |transaction| {
+        transaction.execute("INSERT INTO tags (item_id) VALUES (12345)", [])
+    });
+    let last = submit(db.pool(), |transaction| insert(transaction, "last"));
+
+    for write in [first, dangling, last] {
+        let error = write.await.unwrap().unwrap_err();
+        assert!(matches!(error, Error::GroupCommit(_)), "{error:?}");
+    }
+    assert!(names(&db).await.is_empty());
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn writes_after_shutdown_fail_straight_away() {
+    let db = database(3).await;
+    db.pool().shutdown().await;
+    let result = tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        db.pool()
+            .connect_and_write_grouped(AUTHORIZATION, |transaction| insert(transaction, "late")),
+    )
+    .await
+    .expect("writes after shutdown should not wait for a batch");
+    assert!(matches!(result, Err(Error::ShuttingDown)));
+}
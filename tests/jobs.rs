@@ -0,0 +1,187 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use rocket::{figment::util::map, local::asynchronous::Client, Build, Rocket};
+use rocket_sqlite_rw_pool::{
+    define_database, enqueue_job, testing::TestDatabase, AuthorizedConnector, ConnectionPool,
+    Database, Job, JobFuture, JobHandler, JobQueue, WriteAuthorization,
+};
+
+define_database!(Db, "db", "tests/migrations");
+
+const AUTHORIZATION: WriteAuthorization =
+    WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest;
+
+/// A rocket instance which polls for jobs often, and retries them straight away.
+fn rocket(handlers: Vec<JobHandler<Db>>) -> Rocket<Build> {
+    let figment = rocket::Config::figment()
+        .merge(("log_level", "off"))
+        .merge((
+            "databases.db.jobs",
+            map![
+                "poll_interval" => 10,
+                "max_attempts" => 3,
+                "initial_backoff" => 1,
+                "max_backoff" => 1,
+            ],
+        ));
+    rocket::custom(figment).attach(JobQueue::<Db>::fairing(handlers))
+}
+
+async fn database(handlers: Vec<JobHandler<Db>>) -> TestDatabase<Db> {
+    TestDatabase::builder()
+        .rocket(rocket(handlers))
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn enqueue(pool: &ConnectionPool<Db>, kind: &'static str, count: usize) {
+    pool.connect_and_write(AUTHORIZATION, move |transaction| {
+        for i in 0..count {
+            enqueue_job(&transaction, kind, &i)?;
+        }
+        transaction.commit()
+    })
+    .await
+    .unwrap()
+    .unwrap();
+}
+
+/// Wait for the given query to return true.
+async fn wait_for(pool: &ConnectionPool<Db>, sql: &'static str) {
+    for _ in 0..1000 {
+        let done = pool
+            .connect_and_read(move |connection| connection.query_row(sql, [], |row| row.get(0)))
+            .await
+            .unwrap()
+            .unwrap();
+        if done {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("timed out waiting for {sql}");
+}
+
+static EXCLUSIVE_RUNS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+fn exclusive(_: AuthorizedConnector<'_, Db>, job: Job) -> JobFuture<'_> {
+    Box::pin(async move {
+        EXCLUSIVE_RUNS.lock().unwrap().push(job.id);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        Ok(())
+    })
+}
+
+#[rocket::async_test]
+async fn each_job_is_claimed_once_across_instances() {
+    let handlers = || vec![JobHandler::new("exclusive", exclusive)];
+    let db = database(handlers()).await;
+    // A second instance sharing the same file, as another process would.
+    let rocket = rocket(handlers());
+    let figment = rocket
+        .figment()
+        .clone()
+        .merge(("databases.db.url", db.path().display().to_string()));
+    let other = Client::tracked(Db::attach(rocket.configure(figment)))
+        .await
+        .unwrap();
+
+    enqueue(db.pool(), "exclusive", 50).await;
+    wait_for(
+        db.pool(),
+        "SELECT NOT EXISTS (SELECT 1 FROM rocket_sqlite_rw_pool_jobs)",
+    )
+    .await;
+
+    let mut runs = EXCLUSIVE_RUNS.lock().unwrap().clone();
+    runs.sort_unstable();
+    let mut unique = runs.clone();
+    unique.dedup();
+    assert_eq!(runs, unique);
+    assert_eq!(runs.len(), 50);
+
+    ConnectionPool::<Db>::get_pool(other.rocket())
+        .unwrap()
+        .shutdown()
+        .await;
+    db.close().await;
+}
+
+static RECLAIMED_ATTEMPTS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+fn reclaimed(_: AuthorizedConnector<'_, Db>, job: Job) -> JobFuture<'_> {
+    Box::pin(async move {
+        RECLAIMED_ATTEMPTS.lock().unwrap().push(job.attempt);
+        Ok(())
+    })
+}
+
+#[rocket::async_test]
+async fn expired_leases_are_reclaimed() {
+    // As left behind by a worker which went away during its first attempt.
+    let db = TestDatabase::builder()
+        .rocket(rocket(vec![JobHandler::new("reclaimed", reclaimed)]))
+        .fixture(
+            "INSERT INTO rocket_sqlite_rw_pool_jobs
+                (kind, payload, state, attempts, run_at, lease_until, created_at)
+            VALUES ('reclaimed', 'null', 'running', 1, 0, 0, 0)",
+        )
+        .build()
+        .await
+        .unwrap();
+
+    wait_for(
+        db.pool(),
+        "SELECT NOT EXISTS (SELECT 1 FROM rocket_sqlite_rw_pool_jobs)",
+    )
+    .await;
+    assert_eq!(*RECLAIMED_ATTEMPTS.lock().unwrap(), [2]);
+    db.close().await;
+}
+
+static FAILING_ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+
+fn failing(_: AuthorizedConnector<'_, Db>, _: Job) -> JobFuture<'_> {
+    Box::pin(async move {
+        FAILING_ATTEMPTS.fetch_add(1, Ordering::SeqCst);
+        Err(anyhow!("this job always fails"))
+    })
+}
+
+#[rocket::async_test]
+async fn failing_jobs_end_up_dead_after_their_last_attempt() {
+    let db = database(vec![JobHandler::new("failing", failing)]).await;
+    enqueue(db.pool(), "failing", 1).await;
+    wait_for(
+        db.pool(),
+        "SELECT EXISTS (SELECT 1 FROM rocket_sqlite_rw_pool_jobs WHERE state = 'dead')",
+    )
+    .await;
+
+    let (attempts, last_error): (u32, String) = db
+        .pool()
+        .connect_and_read(|connection| {
+            connection.query_row(
+                "SELECT attempts, last_error FROM rocket_sqlite_rw_pool_jobs",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(attempts, 3);
+    assert_eq!(last_error, "this job always fails");
+    // Dead jobs are not claimed again.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(FAILING_ATTEMPTS.load(Ordering::SeqCst), 3);
+    db.close().await;
+}
@@ -0,0 +1,74 @@
+//! Both arms of `define_database!`, expanded and launched.
+
+use rocket::{Build, Rocket};
+use rocket_sqlite_rw_pool::{testing::TestDatabase, Database};
+
+mod plain {
+    rocket_sqlite_rw_pool::define_database!(Plain, "plain");
+}
+
+mod migrated {
+    rocket_sqlite_rw_pool::define_database!(Migrated, "migrated", "tests/migrations");
+}
+
+use migrated::Migrated;
+use plain::Plain;
+
+fn rocket() -> Rocket<Build> {
+    rocket::custom(rocket::Config::figment().merge(("log_level", "off")))
+}
+
+async fn tables<DB: Database>(db: &TestDatabase<DB>) -> Vec<String> {
+    db.pool()
+        .connect_and_read(|connection| {
+            connection
+                .prepare(
+                    "SELECT name FROM sqlite_schema WHERE name NOT LIKE 'sqlite_%' ORDER BY name",
+                )?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()
+        })
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn plain_database() {
+    let db = TestDatabase::<Plain>::builder()
+        .rocket(
+            rocket()
+                .attach(Plain::jobs_fairing())
+                .attach(Plain::scheduler_fairing()),
+        )
+        .build()
+        .await
+        .unwrap();
+    assert!(Plain::pool(db.client().rocket()).is_some());
+    assert!(Plain::get_one(db.client().rocket()).is_some());
+    let tables = tables(&db).await;
+    assert!(tables.contains(&"rocket_sqlite_rw_pool_jobs".to_owned()));
+    assert!(tables.contains(&"rocket_sqlite_rw_pool_scheduled_tasks".to_owned()));
+    assert!(!tables.contains(&"items".to_owned()));
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn migrated_database() {
+    let db = TestDatabase::<Migrated>::builder()
+        .rocket(
+            rocket()
+                .attach(Migrated::jobs_fairing())
+                .attach(Migrated::scheduler_fairing()),
+        )
+        .build()
+        .await
+        .unwrap();
+    assert!(Migrated::pool(db.client().rocket()).is_some());
+    assert!(Migrated::get_one(db.client().rocket()).is_some());
+    let tables = tables(&db).await;
+    assert!(tables.contains(&"rocket_sqlite_rw_pool_jobs".to_owned()));
+    assert!(tables.contains(&"rocket_sqlite_rw_pool_scheduled_tasks".to_owned()));
+    assert!(tables.contains(&"items".to_owned()));
+    db.close().await;
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use rocket_sqlite_rw_pool::{define_database, testing::TestDatabase, WriteAuthorization};
+
+define_database!(Db, "db", "tests/migrations");
+
+const AUTHORIZATION: WriteAuthorization =
+    WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest;
+
+async fn database() -> TestDatabase<Db> {
+    let figment = rocket::Config::figment().merge(("log_level", "off"));
+    TestDatabase::builder()
+        .rocket(rocket::custom(figment).mount("/", Db::metrics_routes()))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn transactions_are_timed_separately_from_connections() {
+    let db = database().await;
+    // Held for a while, but never inside a transaction.
+    db.pool()
+        .connect_and_read(|_| std::thread::sleep(Duration::from_millis(200)))
+        .await
+        .unwrap();
+    db.pool()
+        .connect_and_read_with_transaction(|transaction| transaction.commit())
+        .await
+        .unwrap()
+        .unwrap();
+    db.pool()
+        .try_connect_and_write(AUTHORIZATION, |transaction| {
+            transaction.execute("INSERT INTO items (name) VALUES ('timed')", [])
+        })
+        .await
+        .unwrap();
+
+    let stats = db.pool().stats();
+    assert_eq!(stats.readers.transactions.count, 1);
+    assert!(stats.readers.transactions.sum < Duration::from_millis(200));
+    assert_eq!(stats.writer.transactions.count, 1);
+
+    let metrics = db
+        .client()
+        .get("/metrics")
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(metrics
+        .contains("sqlite_pool_transaction_seconds_count{database=\"db\",role=\"reader\"} 1\n"));
+    assert!(metrics
+        .contains("sqlite_pool_transaction_seconds_count{database=\"db\",role=\"writer\"} 1\n"));
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn rolled_back_transactions_are_timed() {
+    let db = database().await;
+    db.pool()
+        .try_connect_and_write(AUTHORIZATION, |_| Err::<(), _>("rolled back"))
+        .await
+        .unwrap_err();
+    db.pool()
+        .connect_and_write(AUTHORIZATION, |transaction| drop(transaction))
+        .await
+        .unwrap();
+    assert_eq!(db.pool().stats().writer.transactions.count, 2);
+    db.close().await;
+}
@@ -0,0 +1,5 @@
+-- Checked at commit, so that a write can leave the transaction unable to commit.
+CREATE TABLE tags (
+    id INTEGER PRIMARY KEY,
+    item_id INTEGER NOT NULL REFERENCES items (id) DEFERRABLE INITIALLY DEFERRED
+);
@@ -0,0 +1,62 @@
+use rocket::{response::stream::TextStream, State};
+use rocket_sqlite_rw_pool::{define_database, testing::TestDatabase, ConnectionPool, Connector};
+use rusqlite::Connection;
+
+define_database!(Db, "db", "tests/migrations");
+
+fn count(connection: &Connection) -> i64 {
+    connection
+        .query_row("SELECT count(*) FROM items", [], |row| row.get(0))
+        .unwrap()
+}
+
+#[rocket::get("/twice")]
+async fn twice(connector: Connector<'_, Db>) -> String {
+    let first = connector.connect_and_read(count).await.unwrap();
+    let second = connector.connect_and_read(count).await.unwrap();
+    format!("{first} {second}")
+}
+
+#[rocket::get("/stream")]
+async fn stream(
+    connector: Connector<'_, Db>,
+    pool: &State<ConnectionPool<Db>>,
+) -> TextStream![String] {
+    let before = connector.connect_and_read(count).await.unwrap();
+    let pool = pool.inner().clone();
+    TextStream! {
+        // There is only one reader, so this fails unless the cached one was released.
+        let during = pool.connect_and_read(count).await;
+        yield format!("{before} {during:?}");
+    }
+}
+
+async fn database() -> TestDatabase<Db> {
+    let figment = rocket::Config::figment()
+        .merge(("log_level", "off"))
+        .merge(("databases.db.max_read_connections", 1))
+        .merge(("databases.db.connect_timeout", 1));
+    TestDatabase::builder()
+        .rocket(rocket::custom(figment).mount("/", rocket::routes![twice, stream]))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn reads_in_a_request_share_a_connection() {
+    let db = database().await;
+    let acquired = db.pool().stats().readers.acquired;
+    let response = db.client().get("/twice").dispatch().await;
+    assert_eq!(response.into_string().await.unwrap(), "0 0");
+    assert_eq!(db.pool().stats().readers.acquired, acquired + 1);
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn cached_connection_is_released_before_the_response_is_sent() {
+    let db = database().await;
+    let response = db.client().get("/stream").dispatch().await;
+    assert_eq!(response.into_string().await.unwrap(), "0 Ok(0)");
+    db.close().await;
+}
@@ -0,0 +1,81 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use rocket::{figment::util::map, local::asynchronous::Client, Build, Rocket};
+use rocket_sqlite_rw_pool::{
+    define_database, testing::TestDatabase, AuthorizedConnector, ConnectionPool, Database,
+    Schedule, ScheduledTask, ScheduledTaskFuture, Scheduler,
+};
+
+define_database!(Db, "db", "tests/migrations");
+
+static RUNS: AtomicU32 = AtomicU32::new(0);
+
+fn hourly(_: AuthorizedConnector<'_, Db>) -> ScheduledTaskFuture<'_> {
+    Box::pin(async move {
+        RUNS.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    })
+}
+
+/// A rocket instance which checks its schedules often.
+fn rocket() -> Rocket<Build> {
+    let figment = rocket::Config::figment()
+        .merge(("log_level", "off"))
+        .merge(("databases.db.scheduler", map!["poll_interval" => 10]));
+    rocket::custom(figment).attach(Scheduler::<Db>::fairing(vec![ScheduledTask::new(
+        "hourly",
+        Schedule::Every(Duration::from_secs(3600)),
+        hourly,
+    )]))
+}
+
+#[rocket::async_test]
+async fn two_schedulers_never_both_claim_the_same_run() {
+    let db = TestDatabase::<Db>::builder()
+        .rocket(rocket())
+        .build()
+        .await
+        .unwrap();
+    // A second instance sharing the same file, as another process would.
+    let rocket = rocket();
+    let figment = rocket
+        .figment()
+        .clone()
+        .merge(("databases.db.url", db.path().display().to_string()));
+    let other = Client::tracked(Db::attach(rocket.configure(figment)))
+        .await
+        .unwrap();
+
+    // Both instances find the task due as soon as it is registered, and keep
+    // checking it long after the first run has finished.
+    for _ in 0..1000 {
+        let finished = db
+            .pool()
+            .connect_and_read(|connection| {
+                connection.query_row(
+                    "SELECT last_run_at IS NOT NULL AND lease_until IS NULL
+                    FROM rocket_sqlite_rw_pool_scheduled_tasks WHERE name = 'hourly'",
+                    [],
+                    |row| row.get(0),
+                )
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        if finished {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(RUNS.load(Ordering::SeqCst), 1);
+
+    ConnectionPool::<Db>::get_pool(other.rocket())
+        .unwrap()
+        .shutdown()
+        .await;
+    db.close().await;
+}
@@ -0,0 +1,111 @@
+use rocket::figment::{util::map, Figment};
+use rocket_sqlite_rw_pool::{define_database, testing::TestDatabase, Error, WriteAuthorization};
+
+define_database!(Db, "db", "tests/migrations");
+
+const AUTHORIZATION: WriteAuthorization =
+    WriteAuthorization::IPromiseThisIsABackgroundJobNotTiedToARequest;
+
+/// A query which never finishes on its own.
+const ENDLESS: &str =
+    "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) SELECT count(*) FROM n";
+
+/// A query which finishes quickly, but runs for long enough to check the statement timeout.
+const QUICK: &str =
+    "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 10000) \
+    SELECT count(*) FROM n";
+
+/// Configuration for the tests, which all use a short statement timeout.
+fn figment() -> Figment {
+    rocket::Config::figment()
+        .merge(("log_level", "off"))
+        .merge(("databases.db.statement_timeout", 100))
+}
+
+async fn database(figment: Figment) -> TestDatabase<Db> {
+    TestDatabase::builder()
+        .rocket(rocket::custom(figment))
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn names(db: &TestDatabase<Db>) -> Vec<String> {
+    db.pool()
+        .connect_and_read(|connection| {
+            connection
+                .prepare("SELECT name FROM items ORDER BY name")?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()
+        })
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn slow_write_does_not_time_out_the_rest_of_its_batch() {
+    let db = database(figment().merge((
+        "databases.db.group_commit",
+        map!["max_batch_size" => 10, "max_linger" => 50],
+    )))
+    .await;
+    let pool = db.pool().clone();
+    let slow = tokio::spawn(async move {
+        pool.connect_and_write_grouped(AUTHORIZATION, |transaction| {
+            transaction.execute("INSERT INTO items (name) VALUES ('slow')", [])?;
+            transaction.query_row(ENDLESS, [], |row| row.get::<_, i64>(0))
+        })
+        .await
+    });
+    // Queued behind the slow write, in the same batch.
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let neighbour = db
+        .pool()
+        .connect_and_write_grouped(AUTHORIZATION, |transaction| {
+            transaction.query_row(QUICK, [], |row| row.get::<_, i64>(0))?;
+            transaction.execute("INSERT INTO items (name) VALUES ('neighbour')", [])
+        })
+        .await;
+
+    let slow = slow.await.unwrap().unwrap();
+    assert_eq!(
+        slow.unwrap_err().sqlite_error_code(),
+        Some(rusqlite::ErrorCode::OperationInterrupted)
+    );
+    assert_eq!(neighbour.unwrap().unwrap(), 1);
+    assert_eq!(names(&db).await, ["neighbour"]);
+    assert_eq!(db.pool().stats().writer.statement_timeouts, 1);
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn write_committed_after_a_timeout_is_not_reported_as_failed() {
+    let db = database(figment()).await;
+    let result = db
+        .pool()
+        .connect_and_write(AUTHORIZATION, |transaction| {
+            let interrupted = transaction.query_row(ENDLESS, [], |row| row.get::<_, i64>(0));
+            transaction.execute("INSERT INTO items (name) VALUES ('committed')", [])?;
+            transaction.commit()?;
+            Ok::<_, rusqlite::Error>(interrupted.is_err())
+        })
+        .await;
+    assert!(result.unwrap().unwrap());
+    assert_eq!(names(&db).await, ["committed"]);
+    assert_eq!(db.pool().stats().writer.statement_timeouts, 1);
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn read_which_times_out_is_reported() {
+    let db = database(figment()).await;
+    let result = db
+        .pool()
+        .connect_and_read(|connection| {
+            connection.query_row(ENDLESS, [], |row| row.get::<_, i64>(0))
+        })
+        .await;
+    assert!(matches!(result, Err(Error::StatementTimeout)));
+    db.close().await;
+}
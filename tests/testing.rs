@@ -0,0 +1,43 @@
+use rocket_sqlite_rw_pool::{define_database, testing::TestDatabase};
+
+define_database!(Db, "db", "tests/migrations");
+
+#[rocket::async_test]
+async fn fixtures_are_loaded_and_the_files_removed_on_close() {
+    let db = TestDatabase::<Db>::builder()
+        .rocket(rocket::custom(
+            rocket::Config::figment().merge(("log_level", "off")),
+        ))
+        .fixture("INSERT INTO items (name) VALUES ('inline')")
+        .fixture_file("tests/fixtures/items.sql")
+        .build()
+        .await
+        .unwrap();
+    let dir = db.path().parent().unwrap().to_owned();
+    assert!(db.path().exists());
+
+    let names = db
+        .pool()
+        .connect_and_read(|connection| {
+            connection
+                .prepare("SELECT name FROM items ORDER BY id")?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(names, ["inline", "from a file"]);
+
+    db.close().await;
+    assert!(!dir.exists());
+}
+
+#[rocket::async_test]
+async fn missing_fixture_files_fail_the_build() {
+    let result = TestDatabase::<Db>::builder()
+        .fixture_file("tests/fixtures/missing.sql")
+        .build()
+        .await;
+    assert!(result.is_err());
+}